@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS station_snapshot (
+  lon           INTEGER NOT NULL,
+  lat           INTEGER NOT NULL,
+  max_distance  INTEGER NOT NULL,
+  max_results   INTEGER NOT NULL,
+  data          TEXT NOT NULL,
+  fetched       INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (lon, lat, max_distance, max_results)
+) STRICT, WITHOUT ROWID;
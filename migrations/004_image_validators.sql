@@ -0,0 +1,15 @@
+-- the images are only a cache, so they are simply refetched when needed
+DROP TABLE image;
+
+CREATE TABLE image (
+  x                       INTEGER NOT NULL CHECK ( x > 0 ),
+  y                       INTEGER NOT NULL CHECK ( y > 0 ),
+  z                       INTEGER NOT NULL CHECK ( z BETWEEN 0 AND 20 ),
+  data                    BLOB NOT NULL,
+  hash                    TEXT NOT NULL,
+  upstream_etag           TEXT,
+  upstream_last_modified  TEXT,
+  created                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  checked                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (x, y, z)
+) STRICT, WITHOUT ROWID;
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS webhook (
+  name      TEXT PRIMARY KEY CHECK ( LENGTH(name) > 0 ),
+  format    TEXT NOT NULL CHECK ( format IN ('json', 'ntfy', 'matrix') ),
+  url       TEXT NOT NULL,
+  token     TEXT
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS watch (
+  id          INTEGER PRIMARY KEY,
+  station_id  TEXT,
+  group_name  TEXT REFERENCES station_group(name) ON DELETE CASCADE,
+  kind        TEXT NOT NULL CHECK ( kind IN ('bikes', 'docks') ),
+  threshold   INTEGER NOT NULL CHECK ( threshold BETWEEN 1 AND 100 ),
+  webhook     TEXT NOT NULL REFERENCES webhook(name) ON DELETE CASCADE,
+  expires     INTEGER NOT NULL,
+  satisfied   INTEGER NOT NULL DEFAULT FALSE,
+  fired       INTEGER,
+  CHECK ( (station_id IS NULL) != (group_name IS NULL) )
+) STRICT;
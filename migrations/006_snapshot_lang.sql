@@ -0,0 +1,13 @@
+-- the station names depend on the language, the snapshots are just a cache so they are dropped
+DROP TABLE station_snapshot;
+
+CREATE TABLE station_snapshot (
+  lon           INTEGER NOT NULL,
+  lat           INTEGER NOT NULL,
+  max_distance  INTEGER NOT NULL,
+  max_results   INTEGER NOT NULL,
+  lang          TEXT NOT NULL,
+  data          TEXT NOT NULL,
+  fetched       INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (lon, lat, max_distance, max_results, lang)
+) STRICT, WITHOUT ROWID;
@@ -0,0 +1,11 @@
+-- the snapshots are also stored for the map views (bounding boxes), so the queried area is
+-- identified by a key, eg. "nearest/24941/60171/850/20" or "view/15/18651/9487"
+DROP TABLE station_snapshot;
+
+CREATE TABLE station_snapshot (
+  area     TEXT NOT NULL,
+  lang     TEXT NOT NULL,
+  data     TEXT NOT NULL,
+  fetched  INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (area, lang)
+) STRICT, WITHOUT ROWID;
@@ -0,0 +1,21 @@
+-- the tiles of the dark basemap are cached separately from the light ones
+CREATE TABLE image_style (
+  style                   TEXT NOT NULL CHECK ( style IN ('light', 'dark') ),
+  x                       INTEGER NOT NULL CHECK ( x > 0 ),
+  y                       INTEGER NOT NULL CHECK ( y > 0 ),
+  z                       INTEGER NOT NULL CHECK ( z BETWEEN 0 AND 20 ),
+  data                    BLOB NOT NULL,
+  hash                    TEXT NOT NULL,
+  upstream_etag           TEXT,
+  upstream_last_modified  TEXT,
+  created                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  checked                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (style, x, y, z)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO image_style
+  SELECT 'light', x, y, z, data, hash, upstream_etag, upstream_last_modified, created, checked
+    FROM image;
+
+DROP TABLE image;
+ALTER TABLE image_style RENAME TO image;
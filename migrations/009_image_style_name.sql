@@ -0,0 +1,22 @@
+-- the styles are named after their sources and can be configured, eg. hsl-map or osm
+CREATE TABLE image_named (
+  style                   TEXT NOT NULL CHECK ( LENGTH(style) BETWEEN 1 AND 32 ),
+  x                       INTEGER NOT NULL CHECK ( x > 0 ),
+  y                       INTEGER NOT NULL CHECK ( y > 0 ),
+  z                       INTEGER NOT NULL CHECK ( z BETWEEN 0 AND 20 ),
+  data                    BLOB NOT NULL,
+  hash                    TEXT NOT NULL,
+  upstream_etag           TEXT,
+  upstream_last_modified  TEXT,
+  created                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  checked                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (style, x, y, z)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO image_named
+  SELECT IIF(style = 'light', 'hsl-map', style), x, y, z, data, hash, upstream_etag,
+      upstream_last_modified, created, checked
+    FROM image;
+
+DROP TABLE image;
+ALTER TABLE image_named RENAME TO image;
@@ -0,0 +1,22 @@
+-- the high-dpi (@2x) images are cached separately from the normal ones
+CREATE TABLE image_scaled (
+  style                   TEXT NOT NULL CHECK ( LENGTH(style) BETWEEN 1 AND 32 ),
+  x                       INTEGER NOT NULL CHECK ( x > 0 ),
+  y                       INTEGER NOT NULL CHECK ( y > 0 ),
+  z                       INTEGER NOT NULL CHECK ( z BETWEEN 0 AND 20 ),
+  scale                   INTEGER NOT NULL CHECK ( scale IN (1, 2) ),
+  data                    BLOB NOT NULL,
+  hash                    TEXT NOT NULL,
+  upstream_etag           TEXT,
+  upstream_last_modified  TEXT,
+  created                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  checked                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (style, x, y, z, scale)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO image_scaled
+  SELECT style, x, y, z, 1, data, hash, upstream_etag, upstream_last_modified, created, checked
+    FROM image;
+
+DROP TABLE image;
+ALTER TABLE image_scaled RENAME TO image;
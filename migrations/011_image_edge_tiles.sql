@@ -0,0 +1,23 @@
+-- the tiles in the first column and row (x = 0 or y = 0) are valid too
+CREATE TABLE image_edge (
+  style                   TEXT NOT NULL CHECK ( LENGTH(style) BETWEEN 1 AND 32 ),
+  x                       INTEGER NOT NULL CHECK ( x >= 0 ),
+  y                       INTEGER NOT NULL CHECK ( y >= 0 ),
+  z                       INTEGER NOT NULL CHECK ( z BETWEEN 0 AND 20 ),
+  scale                   INTEGER NOT NULL CHECK ( scale IN (1, 2) ),
+  data                    BLOB NOT NULL,
+  hash                    TEXT NOT NULL,
+  upstream_etag           TEXT,
+  upstream_last_modified  TEXT,
+  created                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  checked                 INTEGER NOT NULL DEFAULT (unixepoch()),
+  PRIMARY KEY (style, x, y, z, scale)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO image_edge
+  SELECT style, x, y, z, scale, data, hash, upstream_etag, upstream_last_modified, created,
+      checked
+    FROM image;
+
+DROP TABLE image;
+ALTER TABLE image_edge RENAME TO image;
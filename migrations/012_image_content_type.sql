@@ -0,0 +1,3 @@
+-- the configured styles may serve other formats than png, eg. jpeg
+ALTER TABLE image ADD COLUMN content_type TEXT NOT NULL DEFAULT 'image/png'
+  CHECK ( content_type LIKE 'image/%' );
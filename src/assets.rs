@@ -0,0 +1,56 @@
+use axum::Router;
+use axum::extract::Request;
+use axum::http::{HeaderValue, header};
+use axum::middleware::{Next, from_fn};
+use axum::response::Response;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use tower_http::services::ServeDir;
+
+/// Directory of the static files, relative to the working directory
+pub const STATIC_DIR: &str = "static";
+
+/// Version of the static assets (a hash of their contents). It is added to the asset urls and
+/// the service worker cache name so that the browsers get the new assets after a deploy.
+pub static ASSET_VERSION: LazyLock<String> = LazyLock::new(|| {
+    hash_dir(Path::new(STATIC_DIR)).unwrap_or_else(|e| {
+        tracing::error!("cannot hash the static files: {e}");
+        String::from(env!("CARGO_PKG_VERSION"))
+    })
+});
+
+fn hash_dir(dir: &Path) -> std::io::Result<String> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+    let mut hasher = Sha256::new();
+    for path in paths.iter().filter(|p| p.is_file()) {
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update(fs::read(path)?);
+    }
+    Ok(format!("{:x}", hasher.finalize())[..12].to_owned())
+}
+
+/// The static files. The versioned urls (`?v=`) never change, so they can be cached for long.
+pub fn static_files() -> Router {
+    Router::new()
+        .fallback_service(ServeDir::new(STATIC_DIR))
+        .layer(from_fn(cache_versioned))
+}
+
+async fn cache_versioned(req: Request, next: Next) -> Response {
+    let versioned = req
+        .uri()
+        .query()
+        .is_some_and(|q| q.split('&').any(|p| p.starts_with("v=")));
+    let mut resp = next.run(req).await;
+    if versioned && resp.status().is_success() {
+        let cache_control = HeaderValue::from_static("max-age=31536000, immutable");
+        resp.headers_mut()
+            .insert(header::CACHE_CONTROL, cache_control);
+    }
+    resp
+}
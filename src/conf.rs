@@ -1,33 +1,241 @@
 use crate::err::Result;
+use crate::systemd;
+use crate::tile::TileStyles;
+use serde::de::DeserializeOwned;
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::{SqlitePool, migrate};
-use std::env;
 use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use std::{env, fmt, fs};
+use tokio::net::{TcpListener, UnixListener};
+use tracing_subscriber::EnvFilter;
 
 pub const DIGITRANSIT_ROUTING_URL: &str = "https://api.digitransit.fi/routing/v2/hsl/gtfs/v1";
-pub const DIGITRANSIT_IMG_URL: &str = "https://cdn.digitransit.fi/map/v3/hsl-map";
+/// Templates of the tile image urls of the built-in styles, see [crate::tile::TileStyles]
+pub const DIGITRANSIT_IMG_URL: &str =
+    "https://cdn.digitransit.fi/map/v3/hsl-map/{z}/{x}/{y}{r}.png";
+pub const DIGITRANSIT_SV_IMG_URL: &str =
+    "https://cdn.digitransit.fi/map/v3/hsl-map-sv/{z}/{x}/{y}{r}.png";
+pub const OSM_IMG_URL: &str = "https://tile.openstreetmap.org/{z}/{x}/{y}.png";
 
 /// Config variables related to the app itself
-#[derive(Debug)]
 pub struct AppConf {
     api_key: String,
     db_url: String,
-    port: u16,
+    /// not needed when systemd passes the listener
+    bind: Option<Bind>,
+    shutdown_timeout: Duration,
+    /// `RUST_LOG`-style directives, eg. `info,bikes::upstream=debug`
+    log_filter: EnvFilter,
+    log_format: LogFormat,
+    tile_styles: TileStyles,
+    /// bearer token of the watch api, which is disabled without it
+    watch_token: Option<String>,
+}
+
+/// Text for the terminal or json (one object per line) for journald/Loki
+#[derive(Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Where the server listens, unless the listener is inherited from systemd
+#[derive(Debug)]
+pub enum Bind {
+    Tcp(SocketAddr),
+    /// `unix:/path/to/socket`
+    Unix(PathBuf),
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
 }
 
-pub fn get_var(var_name: &str) -> Result<String> {
-    env::var(var_name).map_err(|_| format!("environment variable '{var_name}' missing").into())
+/// Keys of the config file and the environment variables that override them
+const KEYS: [(&str, &str); 10] = [
+    ("database_url", "DATABASE_URL"),
+    ("digitransit_api_key", "DIGITRANSIT_API_KEY"),
+    ("port", "PORT"),
+    ("bind", "BIND"),
+    ("shutdown_timeout", "SHUTDOWN_TIMEOUT"),
+    ("log", "RUST_LOG"),
+    ("log_format", "LOG_FORMAT"),
+    ("tile_style", "TILE_STYLE"),
+    ("tile_styles", "TILE_STYLES"),
+    ("watch_token", "WATCH_TOKEN"),
+];
+/// Default for how long (in seconds) the in-flight requests are waited for on shutdown
+const SHUTDOWN_TIMEOUT: u64 = 10;
+
+/// Raw config values from the config file and the environment. The errors are collected so
+/// that all of them can be reported at once.
+struct ConfValues {
+    file: toml::Table,
+    errors: Vec<String>,
+}
+
+impl ConfValues {
+    fn read(path: Option<&Path>) -> Result<Self> {
+        let file = match path {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|e| format!("cannot read config file '{}': {e}", path.display()))?
+                .parse::<toml::Table>()
+                .map_err(|e| format!("invalid config file '{}': {e}", path.display()))?,
+            None => toml::Table::new(),
+        };
+        let errors = file
+            .keys()
+            .filter(|k| !KEYS.iter().any(|(key, _)| key == k))
+            .map(|k| format!("unknown key '{k}'"))
+            .collect();
+        Ok(Self { file, errors })
+    }
+
+    fn var(key: &str) -> &'static str {
+        KEYS.iter()
+            .find(|(k, _)| *k == key)
+            .map_or("", |(_, var)| var)
+    }
+
+    fn is_set(&self, key: &str) -> bool {
+        env::var_os(Self::var(key)).is_some() || self.file.contains_key(key)
+    }
+
+    /// The value from the environment or from the file (strings and integers only)
+    fn get(&mut self, key: &str) -> Option<String> {
+        if let Ok(val) = env::var(Self::var(key)) {
+            return Some(val);
+        }
+        match self.file.get(key)? {
+            toml::Value::String(s) => Some(s.clone()),
+            toml::Value::Integer(i) => Some(i.to_string()),
+            val => {
+                let ty = val.type_str();
+                self.errors
+                    .push(format!("'{key}' must be a string, not {ty}"));
+                None
+            }
+        }
+    }
+
+    fn parse<T: FromStr<Err: fmt::Display>>(&mut self, key: &str) -> Option<T> {
+        let val = self.get(key)?;
+        val.parse()
+            .map_err(|e| self.errors.push(format!("invalid '{key}' ({val}): {e}")))
+            .ok()
+    }
+
+    /// Table from the file, or from the environment as an inline table (without the braces)
+    fn table<T: DeserializeOwned>(&mut self, key: &str) -> Option<T> {
+        let table = match env::var(Self::var(key)) {
+            Ok(val) => val
+                .parse::<toml::Table>()
+                .map_err(|e| self.errors.push(format!("invalid '{key}': {e}")))
+                .ok()?,
+            Err(_) => match self.file.get(key)? {
+                toml::Value::Table(table) => table.clone(),
+                val => {
+                    let ty = val.type_str();
+                    self.errors
+                        .push(format!("'{key}' must be a table, not {ty}"));
+                    return None;
+                }
+            },
+        };
+        table
+            .try_into()
+            .map_err(|e| self.errors.push(format!("invalid '{key}': {e}")))
+            .ok()
+    }
+
+    fn required<T: FromStr<Err: fmt::Display>>(&mut self, key: &str) -> Option<T> {
+        if !self.is_set(key) {
+            let var = Self::var(key);
+            self.errors.push(format!("'{key}' (or {var}) missing"));
+            return None;
+        }
+        self.parse(key)
+    }
 }
 
 impl AppConf {
+    /// Read the config file (from `path` or BIKES_CONFIG, if either is set), overridden by
+    /// the environment variables. All the missing and invalid values are reported at once.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let env_path = env::var_os("BIKES_CONFIG").map(PathBuf::from);
+        let mut values = ConfValues::read(path.or(env_path.as_deref()))?;
+        let db_url = values.required("database_url");
+        let api_key = values.required("digitransit_api_key");
+        // port is validated even if bind overrides it
+        let port = values.parse::<u16>("port");
+        let bind = if values.is_set("bind") {
+            values.parse("bind")
+        } else if values.is_set("port") {
+            port.map(|port| Bind::Tcp(SocketAddr::from(([127, 0, 0, 1], port))))
+        } else {
+            if !systemd::has_listener() {
+                let err = "'port' (or PORT) or 'bind' (or BIND) missing";
+                values.errors.push(err.to_owned());
+            }
+            None
+        };
+        let shutdown_timeout = values.parse("shutdown_timeout").unwrap_or(SHUTDOWN_TIMEOUT);
+        let log_filter = values.parse("log");
+        let log_format = values.parse("log_format").unwrap_or(LogFormat::Text);
+        let tile_styles = values.table("tile_styles").unwrap_or_default();
+        let tile_style = values.parse("tile_style");
+        let watch_token = values.get("watch_token").filter(|t| !t.is_empty());
+        let tile_styles = TileStyles::new(tile_styles, tile_style)
+            .map_err(|e| values.errors.push(format!("invalid 'tile_style': {e}")))
+            .ok();
+        match (db_url, api_key, tile_styles) {
+            (Some(db_url), Some(api_key), Some(tile_styles)) if values.errors.is_empty() => {
+                Ok(Self {
+                    api_key,
+                    db_url,
+                    bind,
+                    shutdown_timeout: Duration::from_secs(shutdown_timeout),
+                    log_filter: log_filter.unwrap_or_else(|| EnvFilter::new("info")),
+                    log_format,
+                    tile_styles,
+                    watch_token,
+                })
+            }
+            _ => Err(values.errors.join("\n").into()),
+        }
+    }
+
+    /// Config from the environment variables (and BIKES_CONFIG)
     pub fn from_env() -> Result<Self> {
-        Ok(Self {
-            db_url: get_var("DATABASE_URL")?,
-            port: get_var("PORT")?.parse()?,
-            api_key: get_var("DIGITRANSIT_API_KEY")?,
-        })
+        Self::load(None)
+    }
+
+    pub fn init_logging(&self) {
+        let filter = EnvFilter::new(self.log_filter.to_string());
+        let fmt = tracing_subscriber::fmt().with_env_filter(filter);
+        match self.log_format {
+            LogFormat::Text => fmt.init(),
+            // the spans include the request id and the upstream call
+            LogFormat::Json => fmt.json().with_span_list(true).init(),
+        }
+    }
+
+    /// How long the in-flight requests are waited for on shutdown
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    pub fn tile_styles(&self) -> TileStyles {
+        self.tile_styles.clone()
+    }
+
+    pub fn watch_token(&self) -> Option<String> {
+        self.watch_token.clone()
     }
 
     /// run last as this takes AppConf as owned
@@ -43,7 +251,96 @@ impl AppConf {
         Ok(pool)
     }
 
-    pub async fn listener(&self) -> Result<TcpListener> {
-        Ok(TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], self.port))).await?)
+    /// Listener passed by systemd socket activation or the configured one
+    pub async fn listener(&self) -> Result<Listener> {
+        if let Some(listener) = systemd::inherited_listener()? {
+            return Ok(listener);
+        }
+        match &self.bind {
+            None => Err("no listener passed by systemd".into()),
+            Some(Bind::Tcp(addr)) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            Some(Bind::Unix(path)) => {
+                // a socket left behind by a previous run would make the bind fail
+                if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    fs::remove_file(path)?;
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+        }
+    }
+}
+
+impl FromStr for Bind {
+    type Err = crate::err::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        s.parse().map(Self::Tcp).map_err(|e| format!("{e}").into())
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(String::from("expected 'text' or 'json'")),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{addr}"),
+            Bind::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Summary of the config, without the api key
+impl fmt::Display for AppConf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "database_url = {}", self.db_url)?;
+        match &self.bind {
+            Some(bind) => writeln!(f, "bind = {bind}")?,
+            None => writeln!(f, "bind = (systemd)")?,
+        }
+        writeln!(f, "shutdown_timeout = {}", self.shutdown_timeout.as_secs())?;
+        writeln!(f, "log = {}", self.log_filter)?;
+        writeln!(f, "log_format = {}", self.log_format)?;
+        writeln!(f, "tile_style = {}", self.tile_styles.default_style())?;
+        let styles: Vec<_> = self.tile_styles.names().map(|s| s.as_str()).collect();
+        writeln!(f, "tile_styles = {}", styles.join(", "))?;
+        if self.watch_token.is_some() {
+            writeln!(f, "watch_token = (set)")?;
+        }
+        write!(f, "digitransit_api_key = (set)")
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addr = match self {
+            Listener::Tcp(l) => l.local_addr().map(|a| a.to_string()),
+            Listener::Unix(l) => l.local_addr().map(|a| {
+                let path = a.as_pathname().map(|p| p.display().to_string());
+                format!("unix:{}", path.unwrap_or_default())
+            }),
+        };
+        write!(f, "{}", addr.unwrap_or_else(|e| e.to_string()))
     }
 }
@@ -0,0 +1,33 @@
+use axum::http::header;
+use axum::http::request::Parts;
+
+/// Value of the named cookie of the request
+pub fn cookie<'a>(parts: &'a Parts, name: &str) -> Option<&'a str> {
+    parts
+        .headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|c| c.to_str().ok())
+        .flat_map(|c| c.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find_map(|(n, value)| (n == name).then_some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    #[test]
+    fn cookies_are_found_by_name() {
+        let req = Request::builder()
+            .header(header::COOKIE, "theme=dark; xlang=sv")
+            .header(header::COOKIE, "lang=fi")
+            .body(())
+            .unwrap();
+        let (parts, _) = req.into_parts();
+        assert_eq!(cookie(&parts, "lang"), Some("fi"));
+        assert_eq!(cookie(&parts, "theme"), Some("dark"));
+        assert_eq!(cookie(&parts, "favorites"), None);
+    }
+}
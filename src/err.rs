@@ -1,12 +1,23 @@
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use std::{error, fmt, io, num, string, time};
 
-#[derive(Debug)]
+/// Errors, categorized by how they are shown to the client
+#[derive(Clone, Debug)]
 pub enum Error {
-    Askama(askama::Error),
-    Axum(axum::Error),
-    AxumHttp(axum::http::Error),
-    Reqwest(reqwest::Error),
-    Other(String),
+    /// The requested resource (eg. a station group) does not exist
+    NotFound(String),
+    /// Malformed or out-of-range request parameters
+    BadRequest(String),
+    /// Missing or wrong token for a protected api
+    Unauthorized(String),
+    /// Upstream (ie. digitransit) returned an error or an invalid response
+    Upstream(String),
+    /// Upstream did not respond in time
+    UpstreamTimeout(String),
+    /// Everything else, eg. database, template or configuration errors
+    Internal(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -26,14 +37,38 @@ macro_rules! err_to_resp {
     };
 }
 
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Error::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Message shown to the user, the details of server-side errors are only logged
+    pub fn user_message(&self) -> &str {
+        match self {
+            Error::NotFound(e) | Error::BadRequest(e) | Error::Unauthorized(e) => e,
+            Error::Upstream(_) => "The bike data service returned an error, try again later",
+            Error::UpstreamTimeout(_) => "The bike data service did not respond in time",
+            Error::Internal(_) => "Something went wrong",
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Askama(e) => write!(f, "{e}"),
-            Error::Axum(e) => write!(f, "{e}"),
-            Error::AxumHttp(e) => write!(f, "{e}"),
-            Error::Reqwest(e) => write!(f, "{e}"),
-            Error::Other(e) => write!(f, "{e}"),
+            Error::NotFound(e) => write!(f, "not found: {e}"),
+            Error::BadRequest(e) => write!(f, "bad request: {e}"),
+            Error::Unauthorized(e) => write!(f, "unauthorized: {e}"),
+            Error::Upstream(e) => write!(f, "upstream error: {e}"),
+            Error::UpstreamTimeout(e) => write!(f, "upstream timeout: {e}"),
+            Error::Internal(e) => write!(f, "{e}"),
         }
     }
 }
@@ -42,94 +77,144 @@ impl error::Error for Error {}
 
 impl From<askama::Error> for Error {
     fn from(value: askama::Error) -> Self {
-        Self::Askama(value)
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<axum::Error> for Error {
     fn from(value: axum::Error) -> Self {
-        Self::Axum(value)
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<axum::http::Error> for Error {
     fn from(value: axum::http::Error) -> Self {
-        Self::AxumHttp(value)
+        Self::Internal(value.to_string())
+    }
+}
+
+impl From<jiff::Error> for Error {
+    fn from(value: jiff::Error) -> Self {
+        Self::Internal(value.to_string())
+    }
+}
+
+impl From<QueryRejection> for Error {
+    fn from(value: QueryRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(value: JsonRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<PathRejection> for Error {
+    fn from(value: PathRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(value: fmt::Error) -> Self {
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<io::Error> for Error {
     fn from(value: io::Error) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<string::FromUtf8Error> for Error {
     fn from(value: string::FromUtf8Error) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<core::num::ParseIntError> for Error {
     fn from(value: core::num::ParseIntError) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<reqwest::Error> for Error {
     fn from(value: reqwest::Error) -> Self {
-        Self::Reqwest(value)
+        if value.is_timeout() {
+            Self::UpstreamTimeout(value.to_string())
+        } else {
+            Self::Upstream(value.to_string())
+        }
     }
 }
 
 impl From<serde_json::Error> for Error {
     fn from(value: serde_json::Error) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<String> for Error {
     fn from(value: String) -> Self {
-        Self::Other(value)
+        Self::Internal(value)
     }
 }
 
 impl From<&str> for Error {
     fn from(value: &str) -> Self {
-        Self::Other(value.to_owned())
+        Self::Internal(value.to_owned())
     }
 }
 
 impl From<sqlx::Error> for Error {
     fn from(value: sqlx::Error) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<sqlx::migrate::MigrateError> for Error {
     fn from(value: sqlx::migrate::MigrateError) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<time::SystemTimeError> for Error {
     fn from(value: time::SystemTimeError) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
 impl From<num::TryFromIntError> for Error {
     fn from(value: num::TryFromIntError) -> Self {
-        Self::Other(value.to_string())
+        Self::Internal(value.to_string())
     }
 }
 
-impl axum::response::IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "Something went wrong".to_string(),
-        )
-            .into_response()
+/// The error itself is stored in the response extensions so that
+/// [crate::page::render_error_page] can turn it into an HTML page.
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let mut resp = (self.status(), self.user_message().to_owned()).into_response();
+        resp.extensions_mut().insert(self);
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_side_details_are_not_shown() {
+        let err = Error::Internal(String::from("db is locked"));
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!err.user_message().contains("db"));
+
+        let err = Error::NotFound(String::from("No group matching the name 'abcd'"));
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+        assert!(err.user_message().contains("abcd"));
     }
 }
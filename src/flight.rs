@@ -0,0 +1,122 @@
+use crate::err::{Error, Result};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tracing::Instrument;
+
+type Call<V> = watch::Receiver<Option<Result<V>>>;
+type Calls<K, V> = Arc<Mutex<HashMap<K, Call<V>>>>;
+
+/// Deduplicates concurrent calls, ie. there is at most one call in flight for each key and
+/// the other callers with the same key wait for its result.
+pub struct SingleFlight<K, V> {
+    calls: Calls<K, V>,
+}
+
+/// Removes the call when it is done, also if it panics. Otherwise the later callers would
+/// keep getting the receiver of the aborted call.
+struct Done<K: Eq + Hash, V> {
+    calls: Calls<K, V>,
+    key: K,
+}
+
+impl<K: Eq + Hash, V> Drop for Done<K, V> {
+    fn drop(&mut self) {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.remove(&self.key);
+        }
+    }
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            calls: Arc::default(),
+        }
+    }
+}
+
+impl<K, V> Clone for SingleFlight<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            calls: self.calls.clone(),
+        }
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Run the future returned by `f`, unless there already is a call with the same key in flight.
+    /// The call is run in a separate task so that it completes even if the caller goes away.
+    pub async fn run<F, Fut>(&self, key: K, f: F) -> Result<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V>> + Send + 'static,
+    {
+        let mut rx = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(&key) {
+                Some(rx) => rx.clone(),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    calls.insert(key.clone(), rx.clone());
+                    let calls = self.calls.clone();
+                    let (done, fut) = (Done { calls, key }, f());
+                    // the call keeps the span (eg. the request id) of the caller that started it
+                    let task = async move {
+                        let res = fut.await;
+                        drop(done);
+                        tx.send_replace(Some(res));
+                    };
+                    tokio::spawn(task.in_current_span());
+                    rx
+                }
+            }
+        };
+        let res = rx
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| Error::Internal(String::from("in-flight call was aborted")))?;
+        res.clone().expect("waited for the result")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn concurrent_calls_are_run_once() {
+        let flight = SingleFlight::default();
+        let n_calls = Arc::new(AtomicU32::new(0));
+        let call = || {
+            let n_calls = n_calls.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(n_calls.fetch_add(1, Ordering::SeqCst))
+            }
+        };
+        let (a, b) = tokio::join!(flight.run(1, call), flight.run(1, call));
+        assert_eq!((a.unwrap(), b.unwrap()), (0, 0));
+
+        // the call is not in flight anymore
+        assert_eq!(flight.run(1, call).await.unwrap(), 1);
+    }
+
+    fn panics() -> Result<u32> {
+        panic!("call failed")
+    }
+
+    #[tokio::test]
+    async fn panicked_calls_are_not_reused() {
+        let flight = SingleFlight::default();
+        assert!(flight.run(1, || async { panics() }).await.is_err());
+        assert_eq!(flight.run(1, || async { Ok(2) }).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::err::Result;
+use crate::upstream;
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use jiff::Timestamp;
+use serde::Serialize;
+use sqlx::{SqlitePool, migrate, query_scalar};
+
+/// How long (in seconds) after the latest successful upstream call a failed call does not yet
+/// make the service unready
+const UPSTREAM_WINDOW: i64 = 5 * 60;
+
+#[derive(Serialize)]
+struct Health {
+    ok: bool,
+    db: Check,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ok: bool,
+    migrations: Migrations,
+    upstream: Upstream,
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Migrations {
+    ok: bool,
+    applied: usize,
+    expected: usize,
+}
+
+#[derive(Serialize)]
+struct Upstream {
+    ok: bool,
+    /// unix times of the latest calls, null if there have been none since startup
+    last_success: Option<i64>,
+    last_failure: Option<i64>,
+}
+
+impl From<Result<()>> for Check {
+    fn from(value: Result<()>) -> Self {
+        Self {
+            ok: value.is_ok(),
+            error: value.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Can the db run queries
+pub async fn check_db(pool: &SqlitePool) -> Result<()> {
+    query_scalar::<_, i64>("SELECT 1").fetch_one(pool).await?;
+    Ok(())
+}
+
+/// Have all the migrations embedded in the binary been applied
+async fn check_migrations(pool: &SqlitePool) -> Migrations {
+    let applied: Vec<i64> = query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+        .fetch_all(pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("{e}");
+            Vec::new()
+        });
+    let migrator = migrate!();
+    let expected: Vec<_> = migrator
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .collect();
+    Migrations {
+        ok: expected.iter().all(|m| applied.contains(&m.version)),
+        applied: applied.len(),
+        expected: expected.len(),
+    }
+}
+
+/// Upstream is fine unless the latest call failed and there has been no successful call lately
+fn check_upstream() -> Upstream {
+    let (last_success, last_failure) = upstream::last_calls();
+    let recent = |t: i64| Timestamp::now().as_second() - t < UPSTREAM_WINDOW;
+    let ok = match (last_success, last_failure) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some(success), Some(failure)) => success > failure || recent(success),
+    };
+    Upstream {
+        ok,
+        last_success,
+        last_failure,
+    }
+}
+
+fn status(ok: bool) -> StatusCode {
+    if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// Liveness, ie. the process responds and the db works
+pub async fn get_healthz(State(pool): State<SqlitePool>) -> Response {
+    let db = Check::from(check_db(&pool).await);
+    let health = Health { ok: db.ok, db };
+    (status(health.ok), Json(health)).into_response()
+}
+
+/// Readiness, ie. the db is up to date and digitransit can be reached
+pub async fn get_readyz(State(pool): State<SqlitePool>) -> Response {
+    let migrations = check_migrations(&pool).await;
+    let upstream = check_upstream();
+    let readiness = Readiness {
+        ok: migrations.ok && upstream.ok,
+        migrations,
+        upstream,
+    };
+    (status(readiness.ok), Json(readiness)).into_response()
+}
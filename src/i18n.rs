@@ -0,0 +1,190 @@
+use crate::cookie::cookie;
+use crate::err::Error;
+use axum::extract::{FromRequestParts, Query, Request};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::LazyLock;
+
+/// How long (in seconds) the language chosen with `?lang=` is remembered
+const LANG_COOKIE_AGE: u32 = 365 * 24 * 60 * 60;
+
+type Catalogue = HashMap<String, String>;
+
+/// Translation catalogues, the english one is used for missing strings
+static CATALOGUES: LazyLock<[Catalogue; 3]> = LazyLock::new(|| {
+    [
+        include_str!("../i18n/fi.toml"),
+        include_str!("../i18n/sv.toml"),
+        include_str!("../i18n/en.toml"),
+    ]
+    .map(|catalogue| toml::from_str(catalogue).expect("valid catalogue"))
+});
+
+/// Language of the UI and the station names
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Lang {
+    Fi,
+    Sv,
+    #[default]
+    En,
+}
+
+#[derive(Deserialize)]
+struct LangParam {
+    lang: Option<String>,
+}
+
+impl Lang {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lang::Fi => "fi",
+            Lang::Sv => "sv",
+            Lang::En => "en",
+        }
+    }
+
+    /// Accepts language tags with a region, eg. `sv-FI`
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "fi" => Some(Lang::Fi),
+            "sv" => Some(Lang::Sv),
+            "en" => Some(Lang::En),
+            _ => None,
+        }
+    }
+
+    /// The supported language with the highest weight in an `Accept-Language` header
+    fn negotiate(accept_language: &str) -> Option<Self> {
+        let weighted = accept_language.split(',').filter_map(|range| {
+            let mut parts = range.split(';');
+            let lang = Self::from_tag(parts.next()?)?;
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            Some((lang, q))
+        });
+        // the first one wins ties
+        let best = weighted.fold(None, |best: Option<(Lang, f32)>, (lang, q)| match best {
+            Some((_, best_q)) if best_q >= q => best,
+            _ => Some((lang, q)),
+        });
+        best.filter(|(_, q)| *q > 0.0).map(|(lang, _)| lang)
+    }
+
+    /// `?lang=` overrides the cookie, which overrides `Accept-Language`
+    fn from_parts(parts: &Parts) -> Option<Self> {
+        let param = Query::<LangParam>::try_from_uri(&parts.uri).ok();
+        if let Some(lang) = param.and_then(|p| Self::from_tag(p.lang.as_deref()?)) {
+            return Some(lang);
+        }
+        if let Some(lang) = cookie(parts, "lang").and_then(Self::from_tag) {
+            return Some(lang);
+        }
+        let accept = parts.headers.get(header::ACCEPT_LANGUAGE)?.to_str().ok()?;
+        Self::negotiate(accept)
+    }
+
+    fn catalogue(&self) -> &'static Catalogue {
+        let i = match self {
+            Lang::Fi => 0,
+            Lang::Sv => 1,
+            Lang::En => 2,
+        };
+        &CATALOGUES[i]
+    }
+
+    /// Translated string, falls back to english and then to the key itself
+    pub fn t<'a>(&self, key: &'a str) -> &'a str {
+        self.catalogue()
+            .get(key)
+            .or_else(|| Lang::En.catalogue().get(key))
+            .map_or(key, String::as_str)
+    }
+
+    /// Translated string with `{}` replaced by the value
+    pub fn t_with(&self, key: &str, value: impl fmt::Display) -> String {
+        self.t(key).replacen("{}", &value.to_string(), 1)
+    }
+
+    /// Eg. "1 bike" or "2 bikes"
+    pub fn bikes(&self, count: u16) -> String {
+        let key = if count == 1 {
+            "bikes_one"
+        } else {
+            "bikes_other"
+        };
+        self.t_with(key, count)
+    }
+
+    /// Message for the error page, the details of client errors are not translated
+    pub fn error(&self, err: &Error) -> String {
+        let key = match err {
+            Error::NotFound(_) | Error::BadRequest(_) | Error::Unauthorized(_) => {
+                return err.user_message().to_owned();
+            }
+            Error::Upstream(_) => "error_upstream",
+            Error::UpstreamTimeout(_) => "error_upstream_timeout",
+            Error::Internal(_) => "error_internal",
+        };
+        self.t(key).to_owned()
+    }
+}
+
+impl fmt::Display for Lang {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Lang {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_parts(parts).unwrap_or_default())
+    }
+}
+
+/// Remember the language chosen with `?lang=` in a cookie so that it is kept when navigating
+pub async fn remember_lang(req: Request, next: Next) -> Response {
+    let param = Query::<LangParam>::try_from_uri(req.uri()).ok();
+    let lang = param.and_then(|p| Lang::from_tag(p.lang.as_deref()?));
+    let mut resp = next.run(req).await;
+    if let Some(lang) = lang {
+        let cookie = format!("lang={lang}; Path=/; Max-Age={LANG_COOKIE_AGE}; SameSite=Lax");
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            resp.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalogues_have_the_same_keys() {
+        let mut en: Vec<_> = Lang::En.catalogue().keys().collect();
+        en.sort();
+        for lang in [Lang::Fi, Lang::Sv] {
+            let mut keys: Vec<_> = lang.catalogue().keys().collect();
+            keys.sort();
+            assert_eq!(keys, en, "{lang}");
+        }
+    }
+
+    #[test]
+    fn negotiate_uses_weights() {
+        assert_eq!(Lang::negotiate("sv-FI,fi;q=0.8,en;q=0.5"), Some(Lang::Sv));
+        assert_eq!(Lang::negotiate("de,en;q=0.3,fi;q=0.7"), Some(Lang::Fi));
+        assert_eq!(Lang::negotiate("de"), None);
+        assert_eq!(Lang::negotiate("fi;q=0"), None);
+    }
+}
@@ -1,12 +1,25 @@
+mod assets;
 mod conf;
+mod cookie;
 mod err;
+mod flight;
+mod health;
+mod i18n;
+mod metrics;
 mod page;
 mod server;
 mod station;
+mod systemd;
+mod tasks;
+mod theme;
 mod tile;
+mod upstream;
+mod watch;
 
 pub use conf::AppConf;
+pub use i18n::Lang;
 pub use page::PageData;
 pub use server::run;
 pub use station::{Station, StationData};
-pub use tile::Tile;
+pub use tile::{Tile, Viewport};
+pub use upstream::Digitransit;
@@ -1,5 +1,47 @@
+use bikes::AppConf;
+use std::env;
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: bikes [--config FILE] [config check]";
+
+enum Cmd {
+    Serve,
+    ConfigCheck,
+}
+
+/// Path of the config file (if given) and the command
+fn parse_args() -> Result<(Option<PathBuf>, Cmd), String> {
+    let mut config = None;
+    let mut rest = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            config = Some(args.next().ok_or(USAGE)?.into());
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config = Some(path.into());
+        } else {
+            rest.push(arg);
+        }
+    }
+    match rest.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => Ok((config, Cmd::Serve)),
+        ["config", "check"] => Ok((config, Cmd::ConfigCheck)),
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
 fn main() {
-    if let Err(e) = bikes::AppConf::from_env().and_then(bikes::run) {
+    let res = parse_args().and_then(|(config, cmd)| {
+        let conf = AppConf::load(config.as_deref()).map_err(|e| e.to_string())?;
+        match cmd {
+            Cmd::Serve => bikes::run(conf).map_err(|e| e.to_string()),
+            Cmd::ConfigCheck => {
+                println!("{conf}");
+                Ok(())
+            }
+        }
+    });
+    if let Err(e) = res {
         eprintln!("{e}");
         std::process::exit(1)
     }
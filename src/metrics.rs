@@ -0,0 +1,236 @@
+use crate::err::Result;
+use crate::err_to_resp;
+use crate::server::AppState;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sqlx::query;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds (in seconds) of the latency histogram buckets
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Metrics that are collected while serving requests, see [get_metrics] for the rest
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Which of the digitransit apis was called
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Api {
+    Routing,
+    Tiles,
+}
+
+impl Api {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Api::Routing => "routing",
+            Api::Tiles => "tiles",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    /// (method, route, status)
+    requests: Mutex<BTreeMap<(String, String, u16), Histogram>>,
+    /// (api, outcome)
+    upstream: Mutex<BTreeMap<(Api, &'static str), Histogram>>,
+    tile_hits: AtomicU64,
+    tile_misses: AtomicU64,
+    station_hits: AtomicU64,
+    station_misses: AtomicU64,
+}
+
+impl Metrics {
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let key = (method.to_owned(), route.to_owned(), status);
+        let mut requests = self.requests.lock().unwrap();
+        requests.entry(key).or_default().observe(latency);
+    }
+
+    /// Record a single call (ie. each retry separately) to an upstream api
+    pub fn observe_upstream(&self, api: Api, success: bool, latency: Duration) {
+        let outcome = if success { "ok" } else { "error" };
+        let mut upstream = self.upstream.lock().unwrap();
+        upstream.entry((api, outcome)).or_default().observe(latency);
+    }
+
+    pub fn tile_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.tile_hits
+        } else {
+            &self.tile_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn station_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.station_hits
+        } else {
+            &self.station_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) -> std::fmt::Result {
+        header(
+            out,
+            "bikes_http_request_duration_seconds",
+            "histogram",
+            "Latency of the handled requests",
+        )?;
+        for ((method, route, status), hist) in self.requests.lock().unwrap().iter() {
+            let labels = format!(
+                r#"method="{}",route="{}",status="{status}""#,
+                escape(method),
+                escape(route)
+            );
+            hist.render(out, "bikes_http_request_duration_seconds", &labels)?;
+        }
+
+        header(
+            out,
+            "bikes_upstream_request_duration_seconds",
+            "histogram",
+            "Latency of the calls to the digitransit apis",
+        )?;
+        for ((api, outcome), hist) in self.upstream.lock().unwrap().iter() {
+            let labels = format!(r#"api="{}",outcome="{outcome}""#, api.as_str());
+            hist.render(out, "bikes_upstream_request_duration_seconds", &labels)?;
+        }
+
+        header(
+            out,
+            "bikes_cache_requests_total",
+            "counter",
+            "Cache lookups by cache and result",
+        )?;
+        let counters = [
+            ("tile", "hit", &self.tile_hits),
+            ("tile", "miss", &self.tile_misses),
+            ("station", "hit", &self.station_hits),
+            ("station", "miss", &self.station_misses),
+        ];
+        for (cache, result, counter) in counters {
+            let n = counter.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                r#"bikes_cache_requests_total{{cache="{cache}",result="{result}"}} {n}"#
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Cumulative latency histogram
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        for (bucket, le) in self.buckets.iter_mut().zip(BUCKETS) {
+            if secs <= le {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) -> std::fmt::Result {
+        for (n, le) in self.buckets.iter().zip(BUCKETS) {
+            writeln!(out, r#"{name}_bucket{{{labels},le="{le}"}} {n}"#)?;
+        }
+        writeln!(out, r#"{name}_bucket{{{labels},le="+Inf"}} {}"#, self.count)?;
+        writeln!(out, "{name}_sum{{{labels}}} {}", self.sum)?;
+        writeln!(out, "{name}_count{{{labels}}} {}", self.count)
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> std::fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} {kind}")
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Middleware for recording the latency of each request by route
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("static", |p| p.as_str())
+        .to_owned();
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let resp = next.run(req).await;
+    metrics().observe_request(&method, &route, resp.status().as_u16(), start.elapsed());
+    resp
+}
+
+async fn render_db_metrics(state: &AppState, out: &mut String) -> Result<()> {
+    let row = query!(
+        r#"SELECT COUNT(*) AS "rows!: i64", COALESCE(SUM(LENGTH(data)), 0) AS "bytes!: i64" FROM image"#
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    let (size, idle) = (state.pool.size(), state.pool.num_idle());
+    let gauges = [
+        (
+            "bikes_tile_cache_rows",
+            "Number of cached tile images",
+            row.rows,
+        ),
+        (
+            "bikes_tile_cache_bytes",
+            "Total size of the cached tile images",
+            row.bytes,
+        ),
+        (
+            "bikes_db_connections",
+            "Open connections in the db pool",
+            i64::from(size),
+        ),
+        (
+            "bikes_db_connections_idle",
+            "Idle connections in the db pool",
+            idle as i64,
+        ),
+    ];
+    for (name, help, value) in gauges {
+        header(out, name, "gauge", help)?;
+        writeln!(out, "{name} {value}")?;
+    }
+    Ok(())
+}
+
+/// Metrics in the prometheus text format
+pub async fn get_metrics(State(state): State<AppState>) -> Response {
+    let mut out = String::new();
+    err_to_resp!(metrics().render(&mut out));
+    err_to_resp!(render_db_metrics(&state, &mut out).await);
+    let headers = [(header::CONTENT_TYPE, "text/plain; version=0.0.4")];
+    (headers, out).into_response()
+}
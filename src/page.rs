@@ -1,10 +1,17 @@
-use crate::err::Result;
+use crate::assets::ASSET_VERSION;
+use crate::err::{Error, Result};
 use crate::err_to_resp;
-use crate::station::{Group, Station, StationData};
-use crate::tile::Tile;
+use crate::i18n::Lang;
+use crate::station::{Favorites, Group, Snapshot, Station};
+use crate::theme::Theme;
+use crate::tile::{DPR_HINT, Dpr, MapStyles, Viewport};
 use askama::Template;
+use axum::extract::{Request, State};
+use axum::http::header::HeaderName;
+use axum::middleware::Next;
 use axum::response::Response;
 use axum::response::{Html, IntoResponse};
+use sqlx::SqlitePool;
 
 /// For rendering the data as HTML
 #[derive(Template)]
@@ -12,48 +19,187 @@ use axum::response::{Html, IntoResponse};
 pub struct Page {
     groups: Vec<Group>,
     data: PageData,
+    lang: Lang,
+    theme: Theme,
+    /// for choosing the scale of the map images
+    dpr: Dpr,
+    /// for the asset urls, see [ASSET_VERSION]
+    version: &'static str,
 }
 
 impl Page {
-    pub fn new(groups: Vec<Group>, data: PageData) -> Self {
-        Self { groups, data }
+    pub fn new(groups: Vec<Group>, data: PageData, lang: Lang) -> Self {
+        Self {
+            groups,
+            data,
+            lang,
+            theme: Theme::default(),
+            dpr: Dpr::default(),
+            version: &ASSET_VERSION,
+        }
+    }
+
+    /// Render the page in the user's theme, the default follows the browser
+    pub fn with_theme(self, theme: Theme) -> Self {
+        Self { theme, ..self }
+    }
+
+    /// Render the map images in the scale of the client's display
+    pub fn with_dpr(self, dpr: Dpr) -> Self {
+        Self { dpr, ..self }
     }
 }
 
+/// Ask the browsers to send the device pixel ratio with the following requests
+const ACCEPT_CH: HeaderName = HeaderName::from_static("accept-ch");
+
 impl IntoResponse for Page {
     fn into_response(self) -> Response {
-        Html(err_to_resp!(self.render())).into_response()
+        let hints = [(ACCEPT_CH, DPR_HINT)];
+        (hints, Html(err_to_resp!(self.render()))).into_response()
+    }
+}
+
+/// Turns an [Error] returned by a page handler into an HTML page that still has the navigation.
+pub async fn render_error_page(
+    State(pool): State<SqlitePool>,
+    lang: Lang,
+    theme: Theme,
+    req: Request,
+    next: Next,
+) -> Response {
+    let resp = next.run(req).await;
+    let Some(err) = resp.extensions().get::<Error>().cloned() else {
+        return resp;
+    };
+    let groups = Group::get_all(&pool).await.unwrap_or_else(|e| {
+        tracing::error!("{e}");
+        vec![]
+    });
+    let page = Page::new(groups, PageData::Error(err.clone()), lang).with_theme(theme);
+    (err.status(), page).into_response()
+}
+
+/// The user's own position (as opposed to the location of a station group)
+#[derive(Clone, Copy, Debug)]
+pub struct Position {
+    pub lon: f64,
+    pub lat: f64,
+    /// in meters, as reported by the browser
+    pub accuracy: Option<f64>,
+}
+
+impl Position {
+    /// The marker is placed even if it is outside the view, since the view can be panned
+    fn marker(&self, view: &Viewport) -> Marker {
+        let (x, y) = view.px_coord(self.lon, self.lat);
+        let px = view.pixels() as f64;
+        // circles larger than the view would not show anything useful
+        let radius = self
+            .accuracy
+            .filter(|m| m.is_finite() && *m > 0.0)
+            .map(|m| view.m_to_px(self.lat, m).min(px).round() as u16);
+        Marker { x, y, radius }
     }
 }
 
-/// There are three separate cases:
+/// "You are here" marker with the coordinates relative to the view, see [Station::pin_loc]
+#[derive(Debug)]
+pub struct Marker {
+    x: f64,
+    y: f64,
+    /// radius of the accuracy circle in pixels
+    radius: Option<u16>,
+}
+
+impl Marker {
+    pub fn loc(&self) -> String {
+        format!("left: {:.1}px; top: {:.1}px;", self.x, self.y)
+    }
+
+    /// css for the accuracy circle, centered on the marker
+    pub fn circle(&self) -> String {
+        let d = 2 * self.radius.unwrap_or(0);
+        format!("{} width: {d}px; height: {d}px;", self.loc())
+    }
+
+    pub fn has_accuracy(&self) -> bool {
+        self.radius.is_some()
+    }
+}
+
+/// There are five separate cases:
 /// - the landing page with no data (except for the station group links that is essentially just a name and the location of the station group)
 /// - page with a known location; this queries for a list of nearby stations and a tile that contains the reference point
 /// - page that essentially gets location from the browser and redirects to a page with a known location
+/// - list of the stations starred by the user, without the map
+/// - error page, which shows what went wrong
 pub enum PageData {
     GetCurrent,
     NoData,
+    Error(Error),
+    Favorites {
+        stations: Vec<Station>,
+    },
     Data {
         stations: Vec<Station>,
-        ref_point: Tile,
-        pixels: u16,
+        view: Viewport,
+        /// the basemaps of the light and dark themes
+        map: MapStyles,
+        /// the reference point, the view is centered on it unless it is moved
+        lon: f64,
+        lat: f64,
+        /// the user's own position
+        here: Option<Marker>,
+        as_of: Option<String>,
+        live_path: String,
+        /// unix time of the data, shown when the page is served offline by the service worker
+        fetched: i64,
     },
 }
 
 impl PageData {
-    /// The interesting case - construct page data from location and list of nearby stations. It turns [StationData] into a vec of [Station] that contain most importantly the distance to the given reference point.
+    /// The interesting case - construct page data from location and list of stations in the view (centered on the reference point and moved by `d`). It turns [crate::StationData] into a vec of [Station] that contain most importantly the distance to the given reference point.
     pub fn with_data(
-        d: (i8, i8),
+        view: Viewport,
+        map: MapStyles,
+        d: (f64, f64),
         lon_deg: f64,
         lat_deg: f64,
-        station_data: StationData,
+        snapshot: Snapshot,
     ) -> Result<Self> {
-        let ref_point = Tile::ref_point(15, lon_deg, lat_deg) + d;
-        let pixels = 350;
+        let (as_of, fetched) = (snapshot.as_of()?, snapshot.fetched);
+        let station_data = snapshot.data.relative_to(lon_deg, lat_deg);
+        let live_path = format!(
+            "/api/nearby-stations/live?lon={lon_deg}&lat={lat_deg}&dx={}&dy={}",
+            d.0, d.1
+        );
         Ok(Self::Data {
-            stations: station_data.into_stations(&ref_point, pixels),
-            ref_point,
-            pixels,
+            stations: station_data.into_stations(&view),
+            view,
+            map,
+            lon: lon_deg,
+            lat: lat_deg,
+            here: None,
+            as_of,
+            live_path,
+            fetched,
         })
     }
+
+    /// Show the user's position on the map
+    pub fn show_position(&mut self, position: Position) {
+        if let Self::Data { view, here, .. } = self {
+            *here = Some(position.marker(view));
+        }
+    }
+
+    /// Star the stations in the favorites
+    pub fn mark_favorites(&mut self, favorites: &Favorites) {
+        if let Self::Data { stations, .. } | Self::Favorites { stations } = self {
+            for station in stations {
+                station.favorite = favorites.contains(&station.id);
+            }
+        }
+    }
 }
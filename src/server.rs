@@ -1,45 +1,182 @@
-use crate::conf::AppConf;
-use crate::err::Result;
-use crate::station::{get_group_stations, get_groups, get_nearby_stations};
-use crate::tile::get_img;
+use crate::assets::{ASSET_VERSION, static_files};
+use crate::conf::{AppConf, Listener};
+use crate::err::{Error, Result};
+use crate::flight::SingleFlight;
+use crate::health::{get_healthz, get_readyz};
+use crate::i18n::remember_lang;
+use crate::metrics::{get_metrics, track_requests};
+use crate::page::render_error_page;
+use crate::station::{
+    StationCache, get_favorites, get_group_stations, get_groups, get_live_stations,
+    get_nearby_stations, get_nearby_stations_json,
+};
+use crate::systemd;
+use crate::tasks::Tasks;
+use crate::tile::{CachedImg, Tile, TileStyle, TileStyles, get_img};
+use crate::upstream::Digitransit;
+use crate::watch::{delete_watch, get_watches, poll_watches, post_watch, require_watch_token};
 use axum::Router;
-use axum::extract::Request;
+use axum::extract::{FromRef, Request};
+use axum::middleware::{from_fn, from_fn_with_state};
 use axum::response::Response;
-use axum::routing::get;
+use axum::routing::{delete, get};
+use sqlx::SqlitePool;
 use std::sync::Arc;
 use std::time::Duration;
-use tower_http::services::ServeDir;
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tokio_util::sync::CancellationToken;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
-use tracing::{Level, Span};
+use tracing::Span;
+
+/// State shared by the handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: SqlitePool,
+    pub digitransit: Digitransit,
+    pub stations: StationCache,
+    pub tiles: SingleFlight<(Tile, TileStyle), CachedImg>,
+    /// Sources of the basemap styles
+    pub tile_styles: TileStyles,
+    /// Required by the watch api, see [require_watch_token]
+    pub watch_token: Option<Arc<str>>,
+    /// Cancelled when the server starts shutting down
+    pub shutdown: CancellationToken,
+}
+
+impl FromRef<AppState> for SqlitePool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
 
 #[tokio::main]
 pub async fn run(app_conf: AppConf) -> Result<()> {
-    tracing_subscriber::fmt::fmt()
-        .with_max_level(Level::INFO)
-        .init();
+    // first, as taking the listener from systemd modifies the environment
+    let listener = app_conf.listener().await?;
+    app_conf.init_logging();
     let make_span_with = TraceLayer::new_for_http().make_span_with(default_span);
     let trace = make_span_with.on_response(log_status);
 
     let pool = app_conf.con_pool().await?;
-    let listener = app_conf.listener().await?;
-    let api_key = Arc::new(app_conf.api_key());
+    let shutdown_timeout = app_conf.shutdown_timeout();
+    let tile_styles = app_conf.tile_styles();
+    let watch_token = app_conf.watch_token().map(Arc::from);
+    let shutdown = CancellationToken::new();
+    let term = signal(SignalKind::terminate())?;
+    tokio::spawn(shutdown_on_signal(term, shutdown.clone()));
+    let state = AppState {
+        pool: pool.clone(),
+        digitransit: Digitransit::new(app_conf.api_key())?,
+        stations: StationCache::default(),
+        tiles: SingleFlight::default(),
+        tile_styles,
+        watch_token,
+        shutdown: shutdown.clone(),
+    };
+    let mut tasks = Tasks::default();
+    let (stations, evict_pool) = (state.stations.clone(), pool.clone());
+    tasks.spawn("station cache eviction", move || {
+        evict_station_cache(stations.clone(), evict_pool.clone())
+    });
+    let watch_state = state.clone();
+    tasks.spawn("watch poller", move || poll_watches(watch_state.clone()));
+    if let Some(interval) = systemd::watchdog_interval() {
+        let pool = pool.clone();
+        tasks.spawn("watchdog", move || {
+            systemd::watchdog(pool.clone(), interval)
+        });
+    }
 
+    // the watches fire the webhooks of the team, so they are not public
+    let watches = Router::new()
+        .route("/api/watches", get(get_watches).post(post_watch))
+        .route("/api/watches/{id}", delete(delete_watch))
+        .route_layer(from_fn_with_state(state.clone(), require_watch_token));
     let app = Router::new()
         .route("/", get(get_groups))
-        .with_state(pool.clone())
         .route("/stations/{name}", get(get_group_stations))
         .route("/nearby-stations", get(get_nearby_stations))
+        .route("/favorites", get(get_favorites))
+        .layer(from_fn_with_state(pool.clone(), render_error_page))
+        .layer(from_fn(remember_lang))
+        .route("/api/nearby-stations", get(get_nearby_stations_json))
+        .route("/api/nearby-stations/live", get(get_live_stations))
+        .merge(watches)
         .route("/img", get(get_img))
-        .with_state((pool, api_key))
-        .fallback_service(ServeDir::new("static"))
-        .layer(trace);
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
+        .with_state(state)
+        .fallback_service(static_files())
+        .layer(from_fn(track_requests))
+        .layer(trace)
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
 
-    tracing::info!("serving on {}", listener.local_addr()?);
-    Ok(axum::serve(listener, app).await?)
+    tracing::info!("serving on {listener}, assets version {}", *ASSET_VERSION);
+    systemd::notify("READY=1")?;
+    let stopped = shutdown.clone().cancelled_owned();
+    let serve = async {
+        match listener {
+            Listener::Tcp(l) => axum::serve(l, app).with_graceful_shutdown(stopped).await,
+            Listener::Unix(l) => axum::serve(l, app).with_graceful_shutdown(stopped).await,
+        }
+    };
+    let deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(shutdown_timeout).await;
+    };
+    let res = tokio::select! {
+        res = serve => res.map_err(Error::from),
+        () = deadline => {
+            tracing::warn!("requests still in flight after {shutdown_timeout:?}, closing them");
+            Ok(())
+        }
+    };
+
+    tasks.shutdown().await;
+    pool.close().await;
+    tracing::info!("shut down");
+    res
+}
+
+/// Stop accepting new connections on SIGTERM or SIGINT, the in-flight requests are drained
+/// and the live streams end
+async fn shutdown_on_signal(mut term: Signal, shutdown: CancellationToken) {
+    tokio::select! {
+        _ = term.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+    tracing::info!("shutting down");
+    if let Err(e) = systemd::notify("STOPPING=1") {
+        tracing::error!("{e}");
+    }
+    shutdown.cancel();
+}
+
+async fn evict_station_cache(stations: StationCache, pool: SqlitePool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+    loop {
+        interval.tick().await;
+        stations.evict(&pool).await;
+    }
 }
 
+/// The request id is set by [SetRequestIdLayer] (unless the proxy already set it)
 fn default_span(request: &Request) -> Span {
-    tracing::info_span!("request", "{} {}", request.method(), request.uri())
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        request_id,
+        "{} {}",
+        request.method(),
+        request.uri()
+    )
 }
 
 fn log_status(response: &Response, latency: Duration, _span: &Span) {
@@ -1,12 +1,20 @@
-use crate::err::Result;
-use crate::page::{Page, PageData};
+use crate::err::{Error, Result};
+use crate::i18n::Lang;
+use crate::page::{Page, PageData, Position};
+use crate::server::AppState;
+use crate::tile::{VIEW_PIXELS, VIEW_ZOOM, Viewport};
+pub use cache::{Snapshot, StationCache, StationQuery};
+pub use favorites::{Favorites, get_favorites};
 pub use group::{Group, get_group_stations, get_groups};
-pub use nearby::get_nearby_stations;
+pub use live::get_live_stations;
+pub use nearby::{get_nearby_stations, get_nearby_stations_json};
 use serde::Deserialize;
-use sqlx::SqlitePool;
-pub use stations::StationData;
+pub use stations::{StationData, StationObs};
 
+mod cache;
+mod favorites;
 mod group;
+mod live;
 mod nearby;
 mod stations;
 
@@ -18,6 +26,8 @@ pub struct Station {
     pub x: u16,
     pub y: u16,
     pub distance: u16,
+    /// starred by the user, see [Favorites]
+    pub favorite: bool,
 }
 
 impl Station {
@@ -26,6 +36,11 @@ impl Station {
         format!("left: {}px; top: {}px;", self.x, self.y)
     }
 
+    /// Catalogue key of the status as text, so that it is not shown only by the colour
+    pub fn status_key(&self) -> String {
+        format!("status_{}", self.count_class())
+    }
+
     /// How many bikes left? Empty / low / mid / high
     pub fn count_class(&self) -> &str {
         if self.count == 0 {
@@ -40,25 +55,72 @@ impl Station {
     }
 }
 
-/// Delta for the given tile from (0,0) (ie. upper left corner) tile
+/// Maximum number of tiles the view can be moved from the reference point
+const MAX_DELTA: f64 = 20.0;
+
+/// Range of the size of the view in pixels, the page picks one that fits the screen
+const MIN_PIXELS: u16 = 200;
+const MAX_PIXELS: u16 = 800;
+
+/// How far (in tiles, can be fractional) the view is moved from the one centered on the
+/// reference point, and how large it is (in pixels)
 #[derive(Deserialize, Debug)]
 pub struct LocDelta {
-    dx: Option<i8>,
-    dy: Option<i8>,
+    dx: Option<f64>,
+    dy: Option<f64>,
+    px: Option<u16>,
+}
+
+impl LocDelta {
+    /// The view centered on the given point, moved by the delta
+    fn view(&self, lon: f64, lat: f64) -> Result<Viewport> {
+        let pixels = self.px.unwrap_or(VIEW_PIXELS);
+        if !(MIN_PIXELS..=MAX_PIXELS).contains(&pixels) {
+            return Err(Error::BadRequest(format!(
+                "view must be {MIN_PIXELS}-{MAX_PIXELS} pixels"
+            )));
+        }
+        Ok(Viewport::new(lon, lat, VIEW_ZOOM, pixels).moved(self.delta()?))
+    }
+
+    /// The query for the stations within the view
+    fn station_query(&self, lon: f64, lat: f64, lang: Lang) -> Result<StationQuery> {
+        Ok(StationQuery::view(self.view(lon, lat)?.tiles(), lang))
+    }
+
+    fn delta(&self) -> Result<(f64, f64)> {
+        let d = (self.dx.unwrap_or(0.0), self.dy.unwrap_or(0.0));
+        // NaN is not within the range either
+        if !(-MAX_DELTA..=MAX_DELTA).contains(&d.0) || !(-MAX_DELTA..=MAX_DELTA).contains(&d.1) {
+            return Err(Error::BadRequest(format!(
+                "view can be moved at most {MAX_DELTA} tiles"
+            )));
+        }
+        Ok(d)
+    }
 }
 
 /// Get all the relevant information for a given location (nearby stations)
 pub async fn mk_stations_page(
     (lon, lat): (f64, f64),
     loc_d: LocDelta,
-    api_key: &str,
-    pool: &SqlitePool,
+    state: &AppState,
+    lang: Lang,
+    favorites: &Favorites,
+    position: Option<Position>,
 ) -> Result<Page> {
-    let d = (loc_d.dx.unwrap_or(0), loc_d.dy.unwrap_or(0));
-    let maxd = d.0.abs().max(d.1.abs()) + 1;
-    let station_data =
-        StationData::get(api_key, lon, lat, maxd as u16 * 850, (maxd + 1) as u8 * 10).await?;
-    let groups = Group::get_all(pool).await?;
-    let data = PageData::with_data(d, lon, lat, station_data)?;
-    Ok(Page::new(groups, data))
+    let q = loc_d.station_query(lon, lat, lang)?;
+    let snapshot = state
+        .stations
+        .get(&state.pool, &state.digitransit, q)
+        .await?;
+    let groups = Group::get_all(&state.pool).await?;
+    let view = loc_d.view(lon, lat)?;
+    let map = state.tile_styles.for_page(lang);
+    let mut data = PageData::with_data(view, map, loc_d.delta()?, lon, lat, snapshot)?;
+    data.mark_favorites(favorites);
+    if let Some(position) = position {
+        data.show_position(position);
+    }
+    Ok(Page::new(groups, data, lang))
 }
@@ -0,0 +1,282 @@
+use super::stations::StationData;
+use crate::err::{Error, Result};
+use crate::flight::SingleFlight;
+use crate::i18n::Lang;
+use crate::metrics::metrics;
+use crate::tile::Tiles;
+use crate::upstream::Digitransit;
+use serde::Serialize;
+use sqlx::{SqlitePool, query};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Rounding of the query location, 1/1000 degrees is approx. 110m (lat) and 55m (lon)
+const PRECISION: f64 = 1000.0;
+/// Responses younger than this (in seconds) are served without querying the api
+const TTL: i64 = 30;
+/// Stale data older than this (in seconds) is not shown
+const MAX_STALE_AGE: i64 = 6 * 60 * 60;
+
+/// Parameters of a stations query, either the stations nearest to a point or the stations
+/// within the map view. The language is that of the station names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StationQuery {
+    area: Area,
+    lang: Lang,
+}
+
+/// The location of a nearest-stations query is rounded so that queries from (almost) the same
+/// location share the results. The view is identified by the tiles covering it, so that the
+/// views that are almost the same also share the results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Area {
+    Nearest {
+        lon: i32,
+        lat: i32,
+        max_distance: u16,
+        max_results: u8,
+    },
+    View(Tiles),
+}
+
+impl StationQuery {
+    pub fn new(lon: f64, lat: f64, max_distance: u16, max_results: u8, lang: Lang) -> Self {
+        let area = Area::Nearest {
+            lon: (lon * PRECISION).round() as i32,
+            lat: (lat * PRECISION).round() as i32,
+            max_distance,
+            max_results,
+        };
+        Self { area, lang }
+    }
+
+    /// The stations within the given tiles (covering the map view)
+    pub fn view(tiles: Tiles, lang: Lang) -> Self {
+        Self {
+            area: Area::View(tiles),
+            lang,
+        }
+    }
+
+    async fn fetch(&self, digitransit: &Digitransit) -> Result<StationData> {
+        match self.area {
+            Area::Nearest {
+                lon,
+                lat,
+                max_distance,
+                max_results,
+            } => {
+                let (lon, lat) = (lon as f64 / PRECISION, lat as f64 / PRECISION);
+                StationData::get(digitransit, lon, lat, max_distance, max_results, self.lang).await
+            }
+            Area::View(tiles) => {
+                StationData::get_in_bbox(digitransit, tiles.bbox(), self.lang).await
+            }
+        }
+    }
+}
+
+impl Area {
+    /// Key of the snapshot in the db
+    fn key(&self) -> String {
+        match self {
+            Area::Nearest {
+                lon,
+                lat,
+                max_distance,
+                max_results,
+            } => format!("nearest/{lon}/{lat}/{max_distance}/{max_results}"),
+            Area::View(Tiles {
+                z,
+                min_x,
+                min_y,
+                max_x,
+                max_y,
+            }) => format!("view/{z}/{min_x}/{min_y}/{max_x}/{max_y}"),
+        }
+    }
+}
+
+/// [StationData] with the time it was fetched from the api
+#[derive(Clone, Debug, Serialize)]
+pub struct Snapshot {
+    #[serde(rename = "stations")]
+    pub data: Arc<StationData>,
+    pub fetched: i64,
+    pub stale: bool,
+}
+
+impl Snapshot {
+    fn new(data: StationData) -> Self {
+        Self {
+            data: Arc::new(data),
+            fetched: jiff::Timestamp::now().as_second(),
+            stale: false,
+        }
+    }
+
+    /// Same stations with the distances calculated from the given point
+    pub fn relative_to(&self, lon: f64, lat: f64) -> Self {
+        Self {
+            data: Arc::new(self.data.relative_to(lon, lat)),
+            ..*self
+        }
+    }
+
+    fn age(&self) -> i64 {
+        jiff::Timestamp::now().as_second() - self.fetched
+    }
+
+    /// Local time (HH:MM) of the fetch, if the data is stale
+    pub fn as_of(&self) -> Result<Option<String>> {
+        if !self.stale {
+            return Ok(None);
+        }
+        let ts = jiff::Timestamp::from_second(self.fetched)?;
+        let time = ts.to_zoned(jiff::tz::TimeZone::system()).strftime("%H:%M");
+        Ok(Some(time.to_string()))
+    }
+}
+
+/// Last successful response for each queried area. These are kept in the db so that (stale)
+/// data can be shown when the api is down. Only the responses younger than [TTL] are kept in
+/// memory and served directly, and concurrent queries for the same area share a single api
+/// call.
+#[derive(Clone, Default)]
+pub struct StationCache {
+    recent: Arc<Mutex<HashMap<StationQuery, Snapshot>>>,
+    in_flight: SingleFlight<StationQuery, Snapshot>,
+}
+
+impl StationCache {
+    /// Get a recent response from the cache or query the api
+    pub async fn get(
+        &self,
+        pool: &SqlitePool,
+        digitransit: &Digitransit,
+        q: StationQuery,
+    ) -> Result<Snapshot> {
+        let cached = self.recent.lock().unwrap().get(&q).cloned();
+        if let Some(snapshot) = cached.filter(|s| s.age() < TTL) {
+            metrics().station_cache(true);
+            return Ok(snapshot);
+        }
+        metrics().station_cache(false);
+        let (cache, pool, digitransit) = (self.clone(), pool.clone(), digitransit.clone());
+        self.in_flight
+            .run(
+                q,
+                || async move { cache.fetch(&pool, &digitransit, q).await },
+            )
+            .await
+    }
+
+    /// Drop the expired responses from memory, and the ones that are too old to be shown even
+    /// as stale data from the db
+    pub async fn evict(&self, pool: &SqlitePool) {
+        self.recent.lock().unwrap().retain(|_, s| s.age() < TTL);
+        if let Err(e) = delete_snapshots(pool).await {
+            tracing::error!("{e}");
+        }
+    }
+
+    /// Query the api, falling back to the last successful response if the api fails
+    async fn fetch(
+        &self,
+        pool: &SqlitePool,
+        digitransit: &Digitransit,
+        q: StationQuery,
+    ) -> Result<Snapshot> {
+        let err = match q.fetch(digitransit).await {
+            Ok(data) => {
+                let snapshot = Snapshot::new(data);
+                self.store(pool, q, &snapshot).await;
+                return Ok(snapshot);
+            }
+            Err(e @ (Error::Upstream(_) | Error::UpstreamTimeout(_))) => e,
+            Err(e) => return Err(e),
+        };
+        match last_known(pool, q).await {
+            Some(snapshot) => {
+                tracing::warn!("{err}, using data fetched at {}", snapshot.fetched);
+                Ok(Snapshot {
+                    stale: true,
+                    ..snapshot
+                })
+            }
+            None => Err(err),
+        }
+    }
+
+    async fn store(&self, pool: &SqlitePool, q: StationQuery, snapshot: &Snapshot) {
+        {
+            // every distinct area adds an entry, so the expired ones are dropped right away
+            let mut recent = self.recent.lock().unwrap();
+            recent.retain(|_, s| s.age() < TTL);
+            recent.insert(q, snapshot.clone());
+        }
+        if let Err(e) = store_snapshot(pool, q, snapshot).await {
+            tracing::error!("{e}");
+        }
+    }
+}
+
+async fn last_known(pool: &SqlitePool, q: StationQuery) -> Option<Snapshot> {
+    let snapshot = load_snapshot(pool, q).await.unwrap_or_else(|e| {
+        tracing::error!("{e}");
+        None
+    });
+    snapshot.filter(|s| s.age() < MAX_STALE_AGE)
+}
+
+async fn delete_snapshots(pool: &SqlitePool) -> Result<()> {
+    query!(
+        "DELETE FROM station_snapshot WHERE fetched < unixepoch() - ?",
+        MAX_STALE_AGE
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn store_snapshot(pool: &SqlitePool, q: StationQuery, snapshot: &Snapshot) -> Result<()> {
+    let data = serde_json::to_string(&snapshot.data)?;
+    let (area, lang) = (q.area.key(), q.lang.code());
+    query!(
+        r#"
+        INSERT INTO station_snapshot (area, lang, data, fetched)
+          VALUES (?, ?, ?, ?)
+          ON CONFLICT(area, lang)
+          DO UPDATE SET data=excluded.data, fetched=excluded.fetched;
+        "#,
+        area,
+        lang,
+        data,
+        snapshot.fetched
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn load_snapshot(pool: &SqlitePool, q: StationQuery) -> Result<Option<Snapshot>> {
+    let (area, lang) = (q.area.key(), q.lang.code());
+    let row = query!(
+        r#"
+        SELECT data, fetched FROM station_snapshot WHERE area = ? AND lang = ?
+        "#,
+        area,
+        lang
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let data: StationData = serde_json::from_str(&row.data)?;
+    Ok(Some(Snapshot {
+        data: Arc::new(data),
+        fetched: row.fetched,
+        stale: true,
+    }))
+}
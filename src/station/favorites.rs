@@ -0,0 +1,92 @@
+use super::{Group, StationData};
+use crate::cookie::cookie;
+use crate::err::Result;
+use crate::err_to_resp;
+use crate::i18n::Lang;
+use crate::page::{Page, PageData};
+use crate::server::AppState;
+use crate::theme::Theme;
+use axum::extract::{FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use std::convert::Infallible;
+
+/// Upper limit for the number of favorites, the rest are ignored
+const MAX_FAVORITES: usize = 20;
+
+/// Ids of the stations starred by the user. They are stored (by `static/favorites.js`) in
+/// the `favorites` cookie, separated by dots.
+#[derive(Debug, Default)]
+pub struct Favorites(Vec<String>);
+
+impl Favorites {
+    fn parse(cookie: &str) -> Self {
+        let ids = cookie
+            .split('.')
+            .filter(|id| !id.is_empty() && id.len() <= 32)
+            .filter(|id| {
+                id.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_:-".contains(c))
+            })
+            .take(MAX_FAVORITES)
+            .map(String::from);
+        Self(ids.collect())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.iter().any(|fav| fav == id)
+    }
+
+    fn ids(&self) -> Vec<&str> {
+        self.0.iter().map(String::as_str).collect()
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Favorites {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        Ok(cookie(parts, "favorites")
+            .map(Self::parse)
+            .unwrap_or_default())
+    }
+}
+
+async fn mk_favorites_page(state: &AppState, lang: Lang, favorites: Favorites) -> Result<Page> {
+    let stations = match favorites.0.is_empty() {
+        true => vec![],
+        false => StationData::get_by_ids(&state.digitransit, &favorites.ids(), lang)
+            .await?
+            .into_list(),
+    };
+    let groups = Group::get_all(&state.pool).await?;
+    let mut data = PageData::Favorites { stations };
+    data.mark_favorites(&favorites);
+    Ok(Page::new(groups, data, lang))
+}
+
+/// Render the current counts of the starred stations
+pub async fn get_favorites(
+    State(state): State<AppState>,
+    lang: Lang,
+    theme: Theme,
+    favorites: Favorites,
+) -> Response {
+    err_to_resp!(mk_favorites_page(&state, lang, favorites).await)
+        .with_theme(theme)
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_skips_invalid_ids() {
+        let favorites = Favorites::parse("001.smoove:022..<script>.003");
+        assert_eq!(favorites.ids(), ["001", "smoove:022", "003"]);
+    }
+}
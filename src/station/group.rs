@@ -1,15 +1,19 @@
 use super::LocDelta;
-use super::mk_stations_page;
-use crate::err::Result;
+use super::{Favorites, mk_stations_page};
+use crate::err::{Error, Result};
 use crate::err_to_resp;
+use crate::i18n::Lang;
 use crate::page::Page;
 use crate::page::PageData;
+use crate::server::AppState;
+use crate::theme::Theme;
+use crate::tile::Dpr;
 use axum::extract::State;
+use axum::extract::rejection::QueryRejection;
 use axum::extract::{Path, Query};
 use axum::response::IntoResponse;
 use axum::response::Response;
 use sqlx::{SqlitePool, query_as};
-use std::sync::Arc;
 
 /// Represents a station group, has a name and location
 pub struct Group {
@@ -47,23 +51,32 @@ impl Group {
         )
         .fetch_optional(con)
         .await?;
-        row.ok_or(format!("No group matching the name '{name}'").into())
+        row.ok_or_else(|| Error::NotFound(format!("No group matching the name '{name}'")))
     }
 }
 
 /// Render all the stations at a given group
 pub async fn get_group_stations(
-    State((pool, api_key)): State<(SqlitePool, Arc<String>)>,
+    State(state): State<AppState>,
+    lang: Lang,
+    theme: Theme,
+    dpr: Dpr,
+    favorites: Favorites,
     Path(grp_name): Path<String>,
-    Query(loc_d): Query<LocDelta>,
+    loc_d: std::result::Result<Query<LocDelta>, QueryRejection>,
 ) -> Response {
-    let grp = err_to_resp!(Group::get_with_name(&pool, &grp_name).await);
-    err_to_resp!(mk_stations_page(grp.lon_lat(), loc_d, api_key.as_ref(), &pool).await)
+    let Query(loc_d) = err_to_resp!(loc_d);
+    let grp = err_to_resp!(Group::get_with_name(&state.pool, &grp_name).await);
+    err_to_resp!(mk_stations_page(grp.lon_lat(), loc_d, &state, lang, &favorites, None).await)
+        .with_theme(theme)
+        .with_dpr(dpr)
         .into_response()
 }
 
 /// Render all the available groups
-pub async fn get_groups(State(pool): State<SqlitePool>) -> Response {
+pub async fn get_groups(State(pool): State<SqlitePool>, lang: Lang, theme: Theme) -> Response {
     let groups = err_to_resp!(Group::get_all(&pool).await);
-    Page::new(groups, PageData::NoData).into_response()
+    Page::new(groups, PageData::NoData, lang)
+        .with_theme(theme)
+        .into_response()
 }
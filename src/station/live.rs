@@ -0,0 +1,52 @@
+use super::LocDelta;
+use super::nearby::CurrentLocation;
+use crate::err::Error;
+use crate::err_to_resp;
+use crate::i18n::Lang;
+use crate::server::AppState;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures_util::{StreamExt, stream};
+use std::time::Duration;
+use tokio::time::{Instant, interval_at};
+
+/// How often the station counts are pushed to the client. The responses come from the
+/// station cache so this does not need to match its ttl.
+const LIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Server-sent events with the current counts of nearby stations, see `static/live.js`
+pub async fn get_live_stations(
+    State(state): State<AppState>,
+    lang: Lang,
+    loc: std::result::Result<Query<CurrentLocation>, QueryRejection>,
+    loc_d: std::result::Result<Query<LocDelta>, QueryRejection>,
+) -> Response {
+    let Query(loc) = err_to_resp!(loc);
+    let Query(loc_d) = err_to_resp!(loc_d);
+    let (lon, lat) = err_to_resp!(loc.required_lon_lat());
+    let q = err_to_resp!(loc_d.station_query(lon, lat, lang));
+
+    // the page was just rendered, so there is no need to send the first update immediately
+    let interval = interval_at(Instant::now() + LIVE_INTERVAL, LIVE_INTERVAL);
+    // the stream would otherwise keep the graceful shutdown waiting
+    let shutdown = state.shutdown.clone().cancelled_owned();
+    let events = stream::unfold(interval, move |mut interval| {
+        let state = state.clone();
+        async move {
+            interval.tick().await;
+            let event = match state.stations.get(&state.pool, &state.digitransit, q).await {
+                Ok(snapshot) => Event::default().json_data(snapshot.relative_to(lon, lat)),
+                Err(e) => {
+                    tracing::error!("{e}");
+                    Ok(Event::default().event("error").data(e.user_message()))
+                }
+            };
+            Some((event.map_err(Error::from), interval))
+        }
+    })
+    .take_until(shutdown);
+    let headers = [("x-accel-buffering", "no")];
+    (headers, Sse::new(events).keep_alive(KeepAlive::default())).into_response()
+}
@@ -1,40 +1,81 @@
-use super::{Group, LocDelta, mk_stations_page};
-use crate::err::Result;
+use super::{Favorites, Group, LocDelta, mk_stations_page};
+use crate::err::{Error, Result};
 use crate::err_to_resp;
-use crate::page::{Page, PageData};
+use crate::i18n::Lang;
+use crate::page::{Page, PageData, Position};
+use crate::server::AppState;
+use crate::theme::Theme;
+use crate::tile::{Dpr, validate_lon_lat};
+use axum::Json;
+use axum::extract::rejection::QueryRejection;
 use axum::extract::{Query, State};
 use axum::response::{IntoResponse, Response};
 use serde::Deserialize;
 use sqlx::SqlitePool;
-use std::sync::Arc;
 
 #[derive(Debug, Deserialize)]
 pub struct CurrentLocation {
     lat: Option<f64>,
     lon: Option<f64>,
+    /// in meters, see `static/pos.js`
+    accuracy: Option<f64>,
 }
 
 impl CurrentLocation {
-    fn lon_lat(&self) -> Option<(f64, f64)> {
-        Some((self.lon?, self.lat?))
+    fn lon_lat(&self) -> Option<Result<(f64, f64)>> {
+        Some(validate_lon_lat(self.lon?, self.lat?))
+    }
+
+    pub fn required_lon_lat(&self) -> Result<(f64, f64)> {
+        self.lon_lat()
+            .unwrap_or(Err(Error::BadRequest(String::from("lat and lon required"))))
     }
 }
 
 /// Render nearby stations (given current location)
 pub async fn get_nearby_stations(
-    State((pool, api_key)): State<(SqlitePool, Arc<String>)>,
-    Query(loc): Query<CurrentLocation>,
-    Query(loc_d): Query<LocDelta>,
+    State(state): State<AppState>,
+    lang: Lang,
+    theme: Theme,
+    dpr: Dpr,
+    favorites: Favorites,
+    loc: std::result::Result<Query<CurrentLocation>, QueryRejection>,
+    loc_d: std::result::Result<Query<LocDelta>, QueryRejection>,
 ) -> Response {
+    let Query(loc) = err_to_resp!(loc);
+    let Query(loc_d) = err_to_resp!(loc_d);
     let page = match loc.lon_lat() {
-        Some(ll) => mk_stations_page(ll, loc_d, api_key.as_ref(), &pool).await,
-        None => mk_get_current_page(&pool).await,
+        Some(ll) => {
+            let (lon, lat) = err_to_resp!(ll);
+            let accuracy = loc.accuracy;
+            let position = Position { lon, lat, accuracy };
+            mk_stations_page((lon, lat), loc_d, &state, lang, &favorites, Some(position)).await
+        }
+        None => mk_get_current_page(&state.pool, lang).await,
     };
-    err_to_resp!(page).into_response()
+    err_to_resp!(page)
+        .with_theme(theme)
+        .with_dpr(dpr)
+        .into_response()
+}
+
+/// Nearby stations as json, with `stale: true` if the api is down and the data is old
+pub async fn get_nearby_stations_json(
+    State(state): State<AppState>,
+    lang: Lang,
+    loc: std::result::Result<Query<CurrentLocation>, QueryRejection>,
+    loc_d: std::result::Result<Query<LocDelta>, QueryRejection>,
+) -> Response {
+    let Query(loc) = err_to_resp!(loc);
+    let Query(loc_d) = err_to_resp!(loc_d);
+    let (lon, lat) = err_to_resp!(loc.required_lon_lat());
+    let q = err_to_resp!(loc_d.station_query(lon, lat, lang));
+    let snapshot = err_to_resp!(state.stations.get(&state.pool, &state.digitransit, q).await);
+    Json(snapshot.relative_to(lon, lat)).into_response()
 }
 
-async fn mk_get_current_page(pool: &SqlitePool) -> Result<Page> {
+async fn mk_get_current_page(pool: &SqlitePool, lang: Lang) -> Result<Page> {
     Group::get_all(pool)
         .await
-        .map(|grps| Page::new(grps, PageData::GetCurrent))
+        .map(|grps| Page::new(grps, PageData::GetCurrent, lang))
 }
@@ -1,46 +1,103 @@
 use super::Station;
 use crate::conf::DIGITRANSIT_ROUTING_URL;
 use crate::err::Result;
-use crate::tile::Tile;
-use serde::Deserialize;
+use crate::i18n::Lang;
+use crate::metrics::Api;
+use crate::tile::{BBox, Viewport};
+use crate::upstream::{self, Digitransit};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+
+/// Bike rental networks of the citybikes
+const NETWORKS: [&str; 2] = ["smoove", "vantaa"];
 
 /// Struct that contains all the station information from the API.
 /// Use [StationData::into_stations] for turning it into a list of stations
-/// renderable in the result
+/// renderable in the result. Serialized as a list of [StationObs].
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StationData(Vec<StationObs>);
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StationObs {
-    id: String,
-    name: String,
-    count: u16,
-    lon: f64,
-    lat: f64,
-    distance: u16,
+    pub id: String,
+    pub name: String,
+    pub count: u16,
+    /// free docks, older snapshots in the db do not have this
+    #[serde(default)]
+    pub spaces: u16,
+    pub lon: f64,
+    pub lat: f64,
+    pub distance: u16,
 }
 
 impl StationData {
+    /// Query stations near the given point.
+    /// The station names are in the given language (if the api has a translation).
     pub async fn get(
-        api_key: &str,
+        digitransit: &Digitransit,
         lon: f64,
         lat: f64,
         max_distance: u16,
         max_results: u8,
+        lang: Lang,
     ) -> Result<Self> {
-        let req = reqwest::Client::new()
-            .post(DIGITRANSIT_ROUTING_URL)
-            .header(reqwest::header::CONTENT_TYPE, "application/graphql")
-            .header("digitransit-subscription-key", api_key)
-            .body(nearest_query(lon, lat, max_distance, max_results));
-        Ok(req.send().await?.json::<StationData>().await?)
+        let body = nearest_query(lon, lat, max_distance, max_results);
+        Ok(graphql::<NearestStations>(digitransit, body, lang).await?.0)
+    }
+
+    /// Query the given stations. Unknown ids are ignored and distances are 0.
+    pub async fn get_by_ids(digitransit: &Digitransit, ids: &[&str], lang: Lang) -> Result<Self> {
+        let body = by_ids_query(ids)?;
+        Ok(graphql::<StationsById>(digitransit, body, lang).await?.0)
+    }
+
+    /// Query a single station, `None` if there is no station with the id. The distance is 0.
+    pub async fn get_by_id(
+        digitransit: &Digitransit,
+        id: &str,
+        lang: Lang,
+    ) -> Result<Option<StationObs>> {
+        let body = by_id_query(id)?;
+        Ok(graphql::<StationById>(digitransit, body, lang).await?.0)
+    }
+
+    /// Query all the stations within the area. The distances are 0, see [Self::relative_to].
+    pub async fn get_in_bbox(digitransit: &Digitransit, bbox: BBox, lang: Lang) -> Result<Self> {
+        let body = bbox_query(bbox);
+        Ok(graphql::<StationsInBBox>(digitransit, body, lang).await?.0)
+    }
+
+    /// Station with the given id, if it is included
+    pub fn find(&self, id: &str) -> Option<&StationObs> {
+        self.0.iter().find(|s| s.id == id)
+    }
+
+    pub fn observations(&self) -> &[StationObs] {
+        &self.0
+    }
+
+    /// Recalculate the distances with respect to the given point (instead of the point that was
+    /// used in the query) and sort the stations accordingly
+    pub fn relative_to(&self, lon: f64, lat: f64) -> Self {
+        let mut obs: Vec<_> = self
+            .0
+            .iter()
+            .map(|s| StationObs {
+                distance: distance_m((lon, lat), (s.lon, s.lat)) as u16,
+                ..s.clone()
+            })
+            .collect();
+        obs.sort_by_key(|s| s.distance);
+        Self(obs)
     }
 
-    /// Calculates the relative coordinate within the tile for each station
-    pub fn into_stations(self, ref_pt: &Tile, px: u16) -> Vec<Station> {
+    /// Calculates the coordinates within the view for each station, the ones outside the view
+    /// are dropped
+    pub fn into_stations(self, view: &Viewport) -> Vec<Station> {
         self.0
             .into_iter()
             .filter_map(|s| {
-                let (x, y) = ref_pt.rel_coord(px, s.lon, s.lat)?;
+                let (x, y) = view.rel_coord(s.lon, s.lat)?;
                 Some(Station {
                     id: s.id,
                     name: s.name,
@@ -48,13 +105,64 @@ impl StationData {
                     x,
                     y,
                     distance: s.distance,
+                    favorite: false,
                 })
             })
             .collect()
     }
+
+    /// Stations for a list without the map (so the coordinates are not used)
+    pub fn into_list(self) -> Vec<Station> {
+        self.0
+            .into_iter()
+            .map(|s| Station {
+                id: s.id,
+                name: s.name,
+                count: s.count,
+                x: 0,
+                y: 0,
+                distance: s.distance,
+                favorite: false,
+            })
+            .collect()
+    }
+}
+
+impl From<Vec<StationObs>> for StationData {
+    fn from(value: Vec<StationObs>) -> Self {
+        Self(value)
+    }
+}
+
+/// Send a query to the routing api. The queries only read data so they are safe to retry.
+async fn graphql<T: DeserializeOwned>(
+    digitransit: &Digitransit,
+    body: String,
+    lang: Lang,
+) -> Result<T> {
+    let req = digitransit
+        .post(DIGITRANSIT_ROUTING_URL)
+        .header(reqwest::header::CONTENT_TYPE, "application/graphql")
+        .header(reqwest::header::ACCEPT_LANGUAGE, lang.code())
+        .body(body);
+    let resp = upstream::send(Api::Routing, req).await?;
+    upstream::check_content_type(&resp, "application/json")?;
+    Ok(resp.json::<T>().await?)
+}
+
+/// Great-circle distance between two points in meters
+fn distance_m((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * 6_371_000.0 * a.sqrt().asin()
 }
 
 fn nearest_query(lon: f64, lat: f64, max_distance: u16, max_results: u8) -> String {
+    // json array of strings is also a valid graphql list
+    let networks = serde_json::json!(NETWORKS);
+
     format!(
         r#"
 {{
@@ -62,20 +170,13 @@ fn nearest_query(lon: f64, lat: f64, max_distance: u16, max_results: u8) -> Stri
     lon: {lon}, lat: {lat}, maxDistance: {max_distance}, maxResults: {max_results},
     filterByPlaceTypes: [VEHICLE_RENT],
     filterByModes: [BICYCLE]
-    filterByNetwork: ["smoove", "vantaa"]
+    filterByNetwork: {networks}
   ) {{
     edges {{
       node {{
         distance
         place {{
-          lat
-          lon
-          ...on BikeRentalStation {{
-            name
-            stationId
-            bikesAvailable
-            stationId
-          }}
+          {RENTAL_STATION}
         }}
       }}
     }}
@@ -85,7 +186,111 @@ fn nearest_query(lon: f64, lat: f64, max_distance: u16, max_results: u8) -> Stri
     )
 }
 
-impl<'de> Deserialize<'de> for StationData {
+fn by_ids_query(ids: &[&str]) -> Result<String> {
+    // json array of strings is also a valid graphql list (with escapes)
+    let ids = serde_json::to_string(ids)?;
+    Ok(format!(
+        r#"
+{{
+  vehicleRentalStations(ids: {ids}) {{
+    {RENTAL_STATION}
+  }}
+}}
+    "#
+    ))
+}
+
+fn by_id_query(id: &str) -> Result<String> {
+    let id = serde_json::to_string(id)?;
+    Ok(format!(
+        r#"
+{{
+  vehicleRentalStation(id: {id}) {{
+    {RENTAL_STATION}
+  }}
+}}
+    "#
+    ))
+}
+
+fn bbox_query(bbox: BBox) -> String {
+    let BBox {
+        min_lon,
+        min_lat,
+        max_lon,
+        max_lat,
+    } = bbox;
+    format!(
+        r#"
+{{
+  vehicleRentalsByBbox(
+    minimumLatitude: {min_lat}, minimumLongitude: {min_lon},
+    maximumLatitude: {max_lat}, maximumLongitude: {max_lon}
+  ) {{
+    {RENTAL_STATION}
+  }}
+}}
+    "#
+    )
+}
+
+/// Fields of a station in all the queries, parsed as [RentalStation]
+const RENTAL_STATION: &str = "...on VehicleRentalStation {
+      name
+      stationId
+      lat
+      lon
+      availableVehicles { total }
+      availableSpaces { total }
+      rentalNetwork { networkId }
+    }";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RentalStation {
+    name: String,
+    station_id: String,
+    lat: f64,
+    lon: f64,
+    available_vehicles: Total,
+    available_spaces: Total,
+    rental_network: Network,
+}
+
+#[derive(Deserialize)]
+struct Total {
+    total: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Network {
+    network_id: String,
+}
+
+impl RentalStation {
+    /// The api also has the stations of other networks (eg. scooters), which are not shown
+    fn is_citybike(&self) -> bool {
+        NETWORKS.contains(&self.rental_network.network_id.as_str())
+    }
+
+    fn into_obs(self, distance: u16) -> StationObs {
+        StationObs {
+            id: self.station_id,
+            name: self.name,
+            count: self.available_vehicles.total,
+            spaces: self.available_spaces.total,
+            lon: self.lon,
+            lat: self.lat,
+            distance,
+        }
+    }
+}
+
+/// Response for [nearest_query]
+struct NearestStations(StationData);
+
+impl<'de> Deserialize<'de> for NearestStations {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
@@ -113,32 +318,158 @@ impl<'de> Deserialize<'de> for StationData {
 
         #[derive(Deserialize)]
         struct Node {
-            place: Place,
+            place: RentalStation,
             distance: u16,
         }
 
-        #[derive(Deserialize)]
-        struct Place {
-            name: String,
-            lat: f64,
-            lon: f64,
-            #[serde(rename = "stationId")]
-            station_id: String,
-            #[serde(rename = "bikesAvailable")]
-            bikes_available: u16,
-        }
         let edges = Wrapper::deserialize(deserializer)?.data.nearest.edges;
         let stations = edges
             .into_iter()
-            .map(|e| StationObs {
-                id: e.node.place.station_id,
-                name: e.node.place.name,
-                count: e.node.place.bikes_available,
-                lon: e.node.place.lon,
-                lat: e.node.place.lat,
-                distance: e.node.distance,
-            })
+            .filter(|e| e.node.place.is_citybike())
+            .map(|e| e.node.place.into_obs(e.node.distance))
             .collect();
-        Ok(Self(stations))
+        Ok(Self(StationData(stations)))
+    }
+}
+
+/// Response for [by_ids_query]
+struct StationsById(StationData);
+
+impl<'de> Deserialize<'de> for StationsById {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            data: Data,
+        }
+
+        #[derive(Deserialize)]
+        struct Data {
+            #[serde(rename = "vehicleRentalStations")]
+            stations: Vec<Option<RentalStation>>,
+        }
+
+        let stations = Wrapper::deserialize(deserializer)?.data.stations;
+        let stations = stations
+            .into_iter()
+            .flatten()
+            .filter(RentalStation::is_citybike)
+            .map(|s| s.into_obs(0));
+        Ok(Self(StationData(stations.collect())))
+    }
+}
+
+/// Response for [by_id_query]
+struct StationById(Option<StationObs>);
+
+impl<'de> Deserialize<'de> for StationById {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            data: Data,
+        }
+
+        #[derive(Deserialize)]
+        struct Data {
+            #[serde(rename = "vehicleRentalStation")]
+            station: Option<RentalStation>,
+        }
+
+        let station = Wrapper::deserialize(deserializer)?.data.station;
+        Ok(Self(
+            station
+                .filter(RentalStation::is_citybike)
+                .map(|s| s.into_obs(0)),
+        ))
+    }
+}
+
+/// Response for [bbox_query]
+struct StationsInBBox(StationData);
+
+impl<'de> Deserialize<'de> for StationsInBBox {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            data: Data,
+        }
+
+        #[derive(Deserialize)]
+        struct Data {
+            #[serde(rename = "vehicleRentalsByBbox")]
+            rentals: Vec<Rental>,
+        }
+
+        /// free floating vehicles are empty objects since only the stations are queried
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Rental {
+            Station(RentalStation),
+            Other(IgnoredAny),
+        }
+
+        let rentals = Wrapper::deserialize(deserializer)?.data.rentals;
+        let stations = rentals
+            .into_iter()
+            .filter_map(|r| match r {
+                Rental::Station(s) => Some(s),
+                Rental::Other(_) => None,
+            })
+            .filter(RentalStation::is_citybike)
+            .map(|s| s.into_obs(0));
+        Ok(Self(StationData(stations.collect())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(id: &str, network: &str) -> serde_json::Value {
+        serde_json::json!({
+            "name": "Kaivopuisto",
+            "stationId": id,
+            "lat": 60.168,
+            "lon": 24.95,
+            "availableVehicles": { "total": 3 },
+            "availableSpaces": { "total": 7 },
+            "rentalNetwork": { "networkId": network },
+        })
+    }
+
+    #[test]
+    fn stations_of_other_networks_are_dropped() {
+        let resp = serde_json::json!({ "data": { "vehicleRentalStations": [
+            station("001", "smoove"), null, station("X1", "scooters"),
+        ]}});
+        let StationsById(data) = serde_json::from_value(resp).unwrap();
+        let ids: Vec<_> = data.observations().iter().map(|s| &s.id).collect();
+        assert_eq!(ids, ["001"]);
+        assert_eq!((data.0[0].count, data.0[0].spaces), (3, 7));
+
+        let resp =
+            serde_json::json!({ "data": { "vehicleRentalStation": station("X1", "scooters") }});
+        let StationById(station) = serde_json::from_value(resp).unwrap();
+        assert!(station.is_none());
+    }
+
+    #[test]
+    fn station_data_is_read_back() {
+        let resp = serde_json::json!({ "data": { "nearest": { "edges": [
+            { "node": { "distance": 120, "place": station("001", "vantaa") } },
+        ]}}});
+        let NearestStations(data) = serde_json::from_value(resp).unwrap();
+        let json = serde_json::to_string(&data).unwrap();
+        let read: StationData = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.observations()[0].distance, 120);
+        assert_eq!(read.observations()[0].id, "001");
     }
 }
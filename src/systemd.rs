@@ -0,0 +1,89 @@
+use crate::conf::Listener;
+use crate::err::Result;
+use crate::health::check_db;
+use sqlx::SqlitePool;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{self, SocketAddr, UnixDatagram};
+use std::time::Duration;
+use std::{env, process};
+
+/// First file descriptor passed by socket activation
+const LISTEN_FDS_START: i32 = 3;
+
+/// Send a state change (eg. `READY=1`) to systemd, does nothing unless running under
+/// a unit with `Type=notify`
+pub fn notify(state: &str) -> Result<()> {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Interval of the systemd watchdog (`WatchdogSec=`), if it is enabled
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+/// Ping the systemd watchdog at half of its interval as long as the db works,
+/// so that systemd restarts the service when it hangs
+pub async fn watchdog(pool: SqlitePool, watchdog_interval: Duration) {
+    let mut interval = tokio::time::interval(watchdog_interval / 2);
+    loop {
+        interval.tick().await;
+        let res = match check_db(&pool).await {
+            Ok(()) => notify("WATCHDOG=1"),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            tracing::error!("watchdog: {e}");
+        }
+    }
+}
+
+/// Number of the sockets passed by systemd socket activation to this process
+fn listen_fds() -> u32 {
+    let for_us = env::var("LISTEN_PID").is_ok_and(|pid| pid == process::id().to_string());
+    let fds = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse().ok());
+    fds.filter(|_| for_us).unwrap_or(0)
+}
+
+/// Does systemd pass the listener, see [inherited_listener]
+pub fn has_listener() -> bool {
+    listen_fds() > 0
+}
+
+/// Listener passed by systemd socket activation (`LISTEN_FDS`), only the first one is used.
+/// Like `sd_listen_fds`, this unsets the variables so that child processes do not take the
+/// sockets, so it must be called before any other threads read the environment.
+pub fn inherited_listener() -> Result<Option<Listener>> {
+    if !has_listener() {
+        return Ok(None);
+    }
+    // SAFETY: called once at startup before any tasks are spawned, see [crate::server::run]
+    unsafe {
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(var);
+        }
+    }
+    // SAFETY: systemd passes the sockets starting from fd 3 and LISTEN_PID makes sure that they
+    // were meant for this process. Nothing else in the process uses the fd.
+    let fd = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) };
+    let unix = net::UnixListener::from(fd);
+    // local_addr fails if the socket is not a unix socket
+    let listener = if unix.local_addr().is_ok() {
+        unix.set_nonblocking(true)?;
+        Listener::Unix(tokio::net::UnixListener::from_std(unix)?)
+    } else {
+        let tcp = std::net::TcpListener::from(OwnedFd::from(unix));
+        tcp.set_nonblocking(true)?;
+        Listener::Tcp(tokio::net::TcpListener::from_std(tcp)?)
+    };
+    Ok(Some(listener))
+}
@@ -0,0 +1,36 @@
+use futures_util::FutureExt;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Delay before restarting a background task that panicked
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// Background tasks (eg. pollers) that are restarted if they panic and stopped on shutdown
+#[derive(Default)]
+pub struct Tasks(JoinSet<()>);
+
+impl Tasks {
+    /// Run the task created by `task`, a new one is created whenever the previous one panics
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.0.spawn(async move {
+            loop {
+                match AssertUnwindSafe(task()).catch_unwind().await {
+                    Ok(()) => return tracing::info!("{name} stopped"),
+                    Err(_) => tracing::error!("{name} panicked, restarting in {RESTART_DELAY:?}"),
+                }
+                tokio::time::sleep(RESTART_DELAY).await;
+            }
+        });
+    }
+
+    /// Stop all the tasks at their next await point
+    pub async fn shutdown(mut self) {
+        self.0.abort_all();
+        while self.0.join_next().await.is_some() {}
+    }
+}
@@ -0,0 +1,64 @@
+use crate::cookie::cookie;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use std::fmt;
+
+/// Colour theme of the UI, chosen with the toggle in the navigation (`static/theme.js`) and
+/// stored in the `theme` cookie. `Auto` follows `prefers-color-scheme`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    Auto,
+    Light,
+    Dark,
+    Contrast,
+}
+
+impl Theme {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Theme::Auto => "auto",
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+            Theme::Contrast => "contrast",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "auto" => Some(Theme::Auto),
+            "light" => Some(Theme::Light),
+            "dark" => Some(Theme::Dark),
+            "contrast" => Some(Theme::Contrast),
+            _ => None,
+        }
+    }
+
+    /// Key of the name in the translation catalogues
+    pub fn key(&self) -> String {
+        format!("theme_{}", self.code())
+    }
+
+    /// Is the dark basemap used in the rendered page. With `Auto` the browser switches to
+    /// the dark one if needed, the high contrast theme uses the light one.
+    pub fn dark_tiles(&self) -> bool {
+        *self == Theme::Dark
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Theme {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(cookie(parts, "theme")
+            .and_then(Self::from_code)
+            .unwrap_or_default())
+    }
+}
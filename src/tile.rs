@@ -1,98 +1,186 @@
-use crate::conf::DIGITRANSIT_IMG_URL;
-use crate::err::Result;
-use crate::err_to_resp;
-use axum::extract::{Query, State};
-use axum::response::{IntoResponse, Response};
+use crate::err::{Error, Result};
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+pub use img::{CachedImg, get_img};
 use serde::Deserialize;
-use sqlx::{SqlitePool, query};
-use std::sync::Arc;
+use std::convert::Infallible;
+pub use style::{MapStyles, TileStyle, TileStyles};
+pub use viewport::{Tiles, Viewport};
+
+mod img;
+mod style;
+mod viewport;
+
+/// Maximum zoom level supported by the map api
+pub const MAX_ZOOM: u8 = 20;
+/// Zoom level of the map view
+pub const VIEW_ZOOM: u8 = 15;
+/// Width (and height) of the map view in pixels, unless the page asks for another size
+pub const VIEW_PIXELS: u16 = 350;
+/// Largest scale of the images, ie. the `@2x` ones
+pub const MAX_SCALE: u8 = 2;
+/// Size of the normal images in pixels
+const TILE_PIXELS: f64 = 256.0;
+
+/// Client hint for the device pixel ratio, the browsers send it once a page has asked for it.
+/// Only the pages use it, the scale of an image is always in its url.
+pub const DPR_HINT: &str = "sec-ch-dpr";
 
 /// Tile in the map, used for querying the images
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
 pub struct Tile {
     pub x: u32,
     pub y: u32,
     pub z: u8,
+    /// 2 for the high-dpi images of 512 × 512 pixels (`dpr=2` in the image urls), see [Dpr]
+    #[serde(rename = "dpr", default = "Tile::default_scale")]
+    pub scale: u8,
 }
 
 impl Tile {
+    pub fn new(x: u32, y: u32, z: u8) -> Self {
+        let scale = Self::default_scale();
+        Self { x, y, z, scale }
+    }
+
+    fn default_scale() -> u8 {
+        1
+    }
+
+    /// The same tile in the given scale
+    pub fn scaled(self, scale: u8) -> Self {
+        let scale = scale.clamp(1, MAX_SCALE);
+        Self { scale, ..self }
+    }
+
+    /// Check that the tile actually exists at the given zoom level
+    pub fn validate(self) -> Result<Self> {
+        if self.z > MAX_ZOOM {
+            return Err(Error::BadRequest(format!(
+                "zoom level {} is larger than {MAX_ZOOM}",
+                self.z
+            )));
+        }
+        let n = 1u32 << self.z;
+        if self.x >= n || self.y >= n {
+            return Err(Error::BadRequest(format!(
+                "tile ({}, {}) out of range for zoom level {}",
+                self.x, self.y, self.z
+            )));
+        }
+        if !(1..=MAX_SCALE).contains(&self.scale) {
+            return Err(Error::BadRequest(format!(
+                "dpr {} is not between 1 and {MAX_SCALE}",
+                self.scale
+            )));
+        }
+        Ok(self)
+    }
+
     /// Reference point, ie. tile with zooming level z containing the given lon,lat -pair
     pub fn ref_point(z: u8, lon_deg: f64, lat_deg: f64) -> Tile {
         let n = 1 << z;
         let x = (lon_x(n, lon_deg) - 0.5) as u32;
         let y = (lat_y(n, lat_deg) - 0.5) as u32;
-        Self { x, y, z }
+        Self::new(x, y, z)
+    }
+
+    /// url for querying from digitransit, see [style::TileUrl]. `{r}` is replaced with
+    /// `@2x` for the high-dpi images.
+    pub fn digitransit_url(&self, img_url: &str) -> String {
+        let r = match self.scale {
+            1 => String::new(),
+            scale => format!("@{scale}x"),
+        };
+        img_url
+            .replace("{z}", &self.z.to_string())
+            .replace("{x}", &self.x.to_string())
+            .replace("{y}", &self.y.to_string())
+            .replace("{r}", &r)
     }
 
-    /// Calculate coordinates with respect to the tile and given resolution (px)
-    pub fn rel_coord(&self, px: u16, lon_deg: f64, lat_deg: f64) -> Option<(u16, u16)> {
-        let n = 1 << self.z;
-        let px = px as f64;
-        let x = (lon_x(n, lon_deg) - self.x as f64) / 2.0 * px;
-        let y = (lat_y(n, lat_deg) - self.y as f64) / 2.0 * px;
-        if x.min(y) < 0.0 || x.max(y) > px {
-            return None;
+    /// path for querying the image from the backend
+    pub fn img_path(&self, style: &TileStyle) -> String {
+        let path = format!("/img?z={}&x={}&y={}&style={style}", self.z, self.x, self.y);
+        match self.scale {
+            1 => path,
+            scale => format!("{path}&dpr={scale}"),
         }
-        Some((x.round() as u16, y.round() as u16))
     }
+}
 
-    /// url for querying from digitransit
-    pub fn digitransit_url(&self, img_url: &str) -> String {
-        format!("{}/{}/{}/{}.png", img_url, self.z, self.x, self.y)
+/// Device pixel ratio of the client, from the `dpr` parameter or the `Sec-CH-DPR` client
+/// hint. 1 if neither is given (or valid). Used for choosing the images of a page.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dpr(f64);
+
+#[derive(Deserialize)]
+struct DprParam {
+    dpr: Option<f64>,
+}
+
+impl Dpr {
+    /// Scale of the images shown at `px` × `px` (css) pixels. The high-dpi images are used
+    /// when the normal ones would be enlarged by more than a fifth.
+    pub fn scale_at(&self, px: f64) -> u8 {
+        if self.0 * px / TILE_PIXELS > 1.2 {
+            MAX_SCALE
+        } else {
+            1
+        }
     }
 
-    /// path for querying the image from the backend
-    pub fn img_path(&self, dx: u32, dy: u32) -> String {
-        format!("/img?z={}&x={}&y={}", self.z, self.x + dx, self.y + dy)
-    }
-
-    async fn get_cached_img(&self, pool: &SqlitePool) -> Result<Option<Vec<u8>>> {
-        let row = query!(
-            r#"SELECT data FROM image WHERE x = ? AND y = ? AND z = ?"#,
-            self.x,
-            self.y,
-            self.z
-        )
-        .fetch_optional(pool)
-        .await?;
-        Ok(row.map(|r| r.data))
-    }
-
-    async fn cache_img(&self, pool: &SqlitePool, data: &[u8]) -> Result<()> {
-        query!(
-            r#"
-            INSERT INTO image (x, y, z, data) VALUES (?, ?, ?, ?)
-              ON CONFLICT(x, y, z)
-              DO UPDATE SET data=excluded.data, created=unixepoch();
-            "#,
-            self.x,
-            self.y,
-            self.z,
-            data
-        )
-        .execute(pool)
-        .await?;
-        Ok(())
-    }
-
-    pub async fn img_request(&self, api_key: &str) -> Result<Vec<u8>> {
-        let req = reqwest::Client::new()
-            .get(self.digitransit_url(DIGITRANSIT_IMG_URL))
-            .header("digitransit-subscription-key", api_key);
-        Ok(req.send().await?.bytes().await?.to_vec())
+    fn from_parts(parts: &Parts) -> Option<Self> {
+        let param = Query::<DprParam>::try_from_uri(&parts.uri).ok();
+        let hint = || {
+            parts
+                .headers
+                .get(DPR_HINT)?
+                .to_str()
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        let dpr = param.and_then(|p| p.dpr).or_else(hint)?;
+        (dpr.is_finite() && dpr > 0.0).then_some(Self(dpr))
+    }
+}
+
+impl Default for Dpr {
+    fn default() -> Self {
+        Self(1.0)
     }
 }
 
-impl std::ops::Add<(i8, i8)> for Tile {
-    type Output = Self;
+impl<S: Send + Sync> FromRequestParts<S> for Dpr {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        Ok(Self::from_parts(parts).unwrap_or_default())
+    }
+}
+
+/// Area between the given longitudes and latitudes (in degrees)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
 
-    fn add(mut self, (dx, dy): (i8, i8)) -> Self::Output {
-        self.x += dx.max(0) as u32;
-        self.x -= dx.min(0).unsigned_abs() as u32;
-        self.y += dy.max(0) as u32;
-        self.y -= dy.min(0).unsigned_abs() as u32;
-        self
+/// Check that the coordinates are within the area covered by the map projection
+pub fn validate_lon_lat(lon_deg: f64, lat_deg: f64) -> Result<(f64, f64)> {
+    if !(-180.0..=180.0).contains(&lon_deg) || !(-85.0511..=85.0511).contains(&lat_deg) {
+        return Err(Error::BadRequest(format!(
+            "coordinates ({lon_deg}, {lat_deg}) out of range"
+        )));
     }
+    Ok((lon_deg, lat_deg))
 }
 
 pub fn lon_x(n: u64, lon_deg: f64) -> f64 {
@@ -107,39 +195,20 @@ pub fn lat_y(n: u64, lat_deg: f64) -> f64 {
 /// approx 600m for zoom level 15, => diagonal is approx 850m
 fn _tile_height_m(n: u64) -> f64 {
     let y = lat_y(n, 60.0) as u32;
-    (_y_lat(n, y) - _y_lat(n, y + 1)) * 110.412 * 1000.0
+    (y_lat(n, y as f64) - y_lat(n, (y + 1) as f64)) * 110.412 * 1000.0
 }
 
-fn _x_lon(n: u64, x: u32) -> f64 {
-    x as f64 / (n as f64) * 360.0 - 180.0
+fn x_lon(n: u64, x: f64) -> f64 {
+    x / (n as f64) * 360.0 - 180.0
 }
 
-fn _y_lat(n: u64, y: u32) -> f64 {
-    let lat_rad = ((1.0 - y as f64 / n as f64 * 2.0) * std::f64::consts::PI)
+fn y_lat(n: u64, y: f64) -> f64 {
+    let lat_rad = ((1.0 - y / n as f64 * 2.0) * std::f64::consts::PI)
         .sinh()
         .atan();
     lat_rad / std::f64::consts::PI * 180.0
 }
 
-async fn cached_img(pool: &SqlitePool, api_key: &str, tile: Tile) -> Result<Vec<u8>> {
-    if let Some(v) = tile.get_cached_img(pool).await? {
-        return Ok(v);
-    }
-    let data = tile.img_request(api_key).await?;
-    tile.cache_img(pool, &data).await?;
-    Ok(data)
-}
-
-/// Get an image for a tile
-pub async fn get_img(
-    State((pool, api_key)): State<(SqlitePool, Arc<String>)>,
-    Query(tile): Query<Tile>,
-) -> Response {
-    let img = err_to_resp!(cached_img(&pool, api_key.as_ref(), tile).await);
-    let headers = [(axum::http::header::CACHE_CONTROL, "max-age=604800")];
-    (headers, img).into_response()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,16 +217,44 @@ mod tests {
     fn lon_x_is_inv_of_x_lon() {
         let n = 2u64.pow(15);
         let x = 18651;
-        let lon = _x_lon(n, x);
+        let lon = x_lon(n, x as f64);
         let x2 = lon_x(n, lon);
         assert!(x2 as u32 == x);
     }
 
+    #[test]
+    fn tiles_outside_the_zoom_level_are_invalid() {
+        assert!(Tile::new(3, 3, 2).validate().is_ok());
+        assert!(Tile::new(4, 3, 2).validate().is_err());
+        assert!(Tile::new(0, 0, 21).validate().is_err());
+        assert!(Tile::new(3, 3, 2).scaled(2).validate().is_ok());
+        let tile = |q| Query::<Tile>::try_from_uri(&format!("/img?{q}").parse().unwrap());
+        assert_eq!(tile("z=2&x=3&y=3").unwrap().0, Tile::new(3, 3, 2));
+        assert_eq!(tile("z=2&x=3&y=3&dpr=2").unwrap().0.scale, 2);
+        assert!(tile("z=2&x=3&y=3&dpr=3").unwrap().0.validate().is_err());
+        assert!(tile("z=2&x=3&y=3&dpr=2.5").is_err());
+    }
+
+    #[test]
+    fn high_dpi_tiles_use_the_2x_images() {
+        let url = "https://maps.example/{z}/{x}/{y}{r}.png";
+        let tile = Tile::new(3, 2, 4);
+        assert_eq!(tile.digitransit_url(url), "https://maps.example/4/3/2.png");
+        let tile = tile.scaled(Dpr(2.625).scale_at(TILE_PIXELS));
+        assert_eq!(
+            tile.digitransit_url(url),
+            "https://maps.example/4/3/2@2x.png"
+        );
+        assert_eq!(Dpr(1.1).scale_at(TILE_PIXELS), 1);
+        assert_eq!(Dpr(2.0).scale_at(175.0), 2);
+        assert_eq!(Dpr(1.0).scale_at(400.0), 2);
+    }
+
     #[test]
     fn lat_y_is_inv_of_y_lat() {
         let n = 2u64.pow(15);
         let y = 9487;
-        let lat = _y_lat(n, y);
+        let lat = y_lat(n, y as f64);
         let y2 = lat_y(n, lat);
         assert!(y2 as u32 == y);
     }
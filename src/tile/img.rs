@@ -0,0 +1,387 @@
+use super::Tile;
+use super::style::{TileStyle, TileUrl};
+use crate::conf::DIGITRANSIT_IMG_URL;
+use crate::err::{Error, Result};
+use crate::err_to_resp;
+use crate::metrics::{Api, metrics};
+use crate::server::AppState;
+use crate::upstream::{self, Digitransit};
+use axum::body::Bytes;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use jiff::Timestamp;
+use jiff::fmt::rfc2822::{DateTimeParser, DateTimePrinter};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::{SqlitePool, query};
+
+/// How long (in seconds) the images can be used without revalidating them
+const MAX_AGE: i64 = 7 * 24 * 60 * 60;
+/// How long the browsers can use an image of the fallback style, see [super::TileStyles::fallback]
+const FALLBACK_MAX_AGE: i64 = 60 * 60;
+
+/// Image of a tile as stored in the db
+#[derive(Clone, Debug)]
+pub struct CachedImg {
+    data: Bytes,
+    /// sha256 of the data, used as the etag
+    hash: String,
+    /// as returned by the source, eg. `image/png`
+    content_type: String,
+    upstream_etag: Option<String>,
+    upstream_last_modified: Option<String>,
+    /// when the data last changed
+    created: i64,
+    /// when the data was last fetched or revalidated from digitransit
+    checked: i64,
+}
+
+/// Basemap style of the requested image, the default one unless given
+#[derive(Deserialize)]
+pub struct StyleParam {
+    style: Option<TileStyle>,
+}
+
+/// Image as returned by digitransit, along with the validators for revalidating it
+struct UpstreamImg {
+    data: Bytes,
+    content_type: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CachedImg {
+    fn is_fresh(&self) -> bool {
+        Timestamp::now().as_second() - self.checked < MAX_AGE
+    }
+
+    fn etag(&self) -> String {
+        format!("\"{}\"", self.hash)
+    }
+
+    /// Does the client already have this version of the image (If-None-Match / If-Modified-Since)
+    fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(etags) = headers.get(header::IF_NONE_MATCH) {
+            let etag = self.etag();
+            return etags.to_str().is_ok_and(|etags| {
+                etags
+                    .split(',')
+                    .map(|e| e.trim().trim_start_matches("W/"))
+                    .any(|e| e == "*" || e == etag)
+            });
+        }
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|since| DateTimeParser::new().parse_timestamp(since.as_bytes()).ok())
+            .is_some_and(|since| self.created <= since.as_second())
+    }
+
+    fn headers(&self, max_age: i64) -> Result<[(header::HeaderName, String); 4]> {
+        let created = Timestamp::from_second(self.created)?;
+        Ok([
+            (header::CONTENT_TYPE, self.content_type.clone()),
+            (header::CACHE_CONTROL, format!("max-age={max_age}")),
+            (header::ETAG, self.etag()),
+            (
+                header::LAST_MODIFIED,
+                DateTimePrinter::new().timestamp_to_rfc9110_string(&created)?,
+            ),
+        ])
+    }
+
+    async fn get(pool: &SqlitePool, tile: Tile, style: &TileStyle) -> Result<Option<Self>> {
+        let style = style.as_str();
+        let row = query!(
+            r#"
+            SELECT data, hash, content_type, upstream_etag, upstream_last_modified, created,
+                checked
+              FROM image WHERE style = ? AND x = ? AND y = ? AND z = ? AND scale = ?
+            "#,
+            style,
+            tile.x,
+            tile.y,
+            tile.z,
+            tile.scale
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(row.map(|r| Self {
+            data: Bytes::from(r.data),
+            hash: r.hash,
+            content_type: r.content_type,
+            upstream_etag: r.upstream_etag,
+            upstream_last_modified: r.upstream_last_modified,
+            created: r.created,
+            checked: r.checked,
+        }))
+    }
+
+    /// Store the image, the creation time only changes if the contents change
+    async fn store(
+        pool: &SqlitePool,
+        tile: Tile,
+        style: &TileStyle,
+        img: UpstreamImg,
+    ) -> Result<Self> {
+        let hash = format!("{:x}", Sha256::digest(&img.data));
+        let data = img.data.as_ref();
+        let style = style.as_str();
+        let row = query!(
+            r#"
+            INSERT INTO image (style, x, y, z, scale, data, hash, content_type, upstream_etag,
+                upstream_last_modified)
+              VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+              ON CONFLICT(style, x, y, z, scale)
+              DO UPDATE SET data=excluded.data, hash=excluded.hash,
+                content_type=excluded.content_type,
+                upstream_etag=excluded.upstream_etag,
+                upstream_last_modified=excluded.upstream_last_modified,
+                created=IIF(hash = excluded.hash, created, unixepoch()),
+                checked=unixepoch()
+              RETURNING created, checked;
+            "#,
+            style,
+            tile.x,
+            tile.y,
+            tile.z,
+            tile.scale,
+            data,
+            hash,
+            img.content_type,
+            img.etag,
+            img.last_modified
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(Self {
+            data: img.data,
+            hash,
+            content_type: img.content_type,
+            upstream_etag: img.etag,
+            upstream_last_modified: img.last_modified,
+            created: row.created,
+            checked: row.checked,
+        })
+    }
+
+    /// Mark the image as revalidated
+    async fn touch(mut self, pool: &SqlitePool, tile: Tile, style: &TileStyle) -> Result<Self> {
+        let style = style.as_str();
+        let row = query!(
+            r#"
+            UPDATE image SET checked = unixepoch()
+              WHERE style = ? AND x = ? AND y = ? AND z = ? AND scale = ?
+              RETURNING checked
+            "#,
+            style,
+            tile.x,
+            tile.y,
+            tile.z,
+            tile.scale
+        )
+        .fetch_one(pool)
+        .await?;
+        self.checked = row.checked;
+        Ok(self)
+    }
+}
+
+impl Tile {
+    /// Fetch the image from digitransit (in the default style), errors unless the response
+    /// is an image
+    pub async fn img_request(&self, digitransit: &Digitransit) -> Result<Bytes> {
+        let url: TileUrl = DIGITRANSIT_IMG_URL.parse()?;
+        let img = self
+            .conditional_img_request(digitransit, &url, None)
+            .await?;
+        img.map(|img| img.data)
+            .ok_or_else(|| Error::Upstream(String::from("unexpected 304 Not Modified")))
+    }
+
+    /// Fetch the image, unless it has not changed since the previous version (returns None)
+    async fn conditional_img_request(
+        &self,
+        digitransit: &Digitransit,
+        url: &TileUrl,
+        prev: Option<&CachedImg>,
+    ) -> Result<Option<UpstreamImg>> {
+        let mut req = match url.is_digitransit() {
+            true => digitransit.get(url.url(*self)),
+            false => digitransit.get_public(url.url(*self)),
+        };
+        if let Some(etag) = prev.and_then(|p| p.upstream_etag.as_ref()) {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = prev.and_then(|p| p.upstream_last_modified.as_ref()) {
+            req = req.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let resp = upstream::send(Api::Tiles, req).await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        upstream::check_content_type(&resp, "image/")?;
+        let header_str = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        let (etag, last_modified) = (header_str(header::ETAG), header_str(header::LAST_MODIFIED));
+        // checked above
+        let content_type = header_str(header::CONTENT_TYPE).unwrap_or_default();
+        Ok(Some(UpstreamImg {
+            data: resp.bytes().await?,
+            content_type,
+            etag,
+            last_modified,
+        }))
+    }
+}
+
+/// Get the image from the db, fetching or revalidating it from digitransit when needed.
+/// There is at most one request in flight for each tile, concurrent requests for the same
+/// tile wait for its result.
+async fn cached_img(state: &AppState, tile: Tile, style: &TileStyle) -> Result<CachedImg> {
+    let (style, source) = state.tile_styles.get(Some(style.clone()))?;
+    let tile = match source.url.has_scale() {
+        true => tile,
+        false => tile.scaled(1),
+    };
+    if let Some(img) = CachedImg::get(&state.pool, tile, &style).await?
+        && img.is_fresh()
+    {
+        metrics().tile_cache(true);
+        return Ok(img);
+    }
+    metrics().tile_cache(false);
+    let (pool, digitransit) = (state.pool.clone(), state.digitransit.clone());
+    let url = source.url.clone();
+    let key = (tile, style.clone());
+    let refresh = || async move {
+        // another request might have refreshed the image since the previous check
+        let prev = CachedImg::get(&pool, tile, &style).await?;
+        if let Some(img) = prev.as_ref().filter(|img| img.is_fresh()) {
+            return Ok(img.clone());
+        }
+        match (
+            tile.conditional_img_request(&digitransit, &url, prev.as_ref())
+                .await,
+            prev,
+        ) {
+            (Ok(Some(img)), _) => CachedImg::store(&pool, tile, &style, img).await,
+            (Ok(None), Some(prev)) => prev.touch(&pool, tile, &style).await,
+            (Ok(None), None) => Err(Error::Upstream(String::from("unexpected 304 Not Modified"))),
+            (Err(e), Some(prev)) => {
+                tracing::warn!("{e}, using the expired image");
+                Ok(prev)
+            }
+            (Err(e), None) => Err(e),
+        }
+    };
+    state.tiles.run(key, refresh).await
+}
+
+/// Get an image for a tile
+pub async fn get_img(
+    State(state): State<AppState>,
+    req_headers: HeaderMap,
+    tile: std::result::Result<Query<Tile>, QueryRejection>,
+    style: std::result::Result<Query<StyleParam>, QueryRejection>,
+) -> Response {
+    let Query(tile) = err_to_resp!(tile);
+    let Query(StyleParam { style }) = err_to_resp!(style);
+    let tile = err_to_resp!(tile.validate());
+    let (style, _) = err_to_resp!(state.tile_styles.get(style));
+    // a tile of another style is better than a hole in the map, but it is not kept for long
+    let (img, max_age) = match cached_img(&state, tile, &style).await {
+        Err(e) if let Some(fallback) = state.tile_styles.fallback(&style) => {
+            tracing::warn!("{e}, using the {fallback} style");
+            (
+                err_to_resp!(cached_img(&state, tile, &fallback).await),
+                FALLBACK_MAX_AGE,
+            )
+        }
+        img => (err_to_resp!(img), MAX_AGE),
+    };
+    let headers = err_to_resp!(img.headers(max_age));
+    if img.not_modified(&req_headers) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    (headers, img.data).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::migrate;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    fn img(created: i64) -> CachedImg {
+        CachedImg {
+            data: Bytes::new(),
+            hash: String::from("abc"),
+            content_type: String::from("image/png"),
+            upstream_etag: None,
+            upstream_last_modified: None,
+            created,
+            checked: created,
+        }
+    }
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        HeaderMap::from_iter([(name, value.parse().unwrap())])
+    }
+
+    #[test]
+    fn not_modified_checks_the_validators() {
+        let img = img(1_700_000_000);
+        let etags = |v| img.not_modified(&headers(header::IF_NONE_MATCH, v));
+        assert!(etags("\"abc\""));
+        assert!(etags("\"xyz\", W/\"abc\""));
+        assert!(etags("*"));
+        assert!(!etags("\"xyz\", \"ab\""));
+        let since = |v| img.not_modified(&headers(header::IF_MODIFIED_SINCE, v));
+        assert!(since("Tue, 14 Nov 2023 22:13:20 GMT"));
+        assert!(since("Wed, 15 Nov 2023 00:00:00 GMT"));
+        assert!(!since("Tue, 14 Nov 2023 22:13:19 GMT"));
+        assert!(!since("yesterday"));
+        // the etag wins over the date
+        let mut both = headers(header::IF_NONE_MATCH, "\"xyz\"");
+        both.extend(headers(
+            header::IF_MODIFIED_SINCE,
+            "Wed, 15 Nov 2023 00:00:00 GMT",
+        ));
+        assert!(!img.not_modified(&both));
+        assert!(!img.not_modified(&HeaderMap::new()));
+    }
+
+    #[tokio::test]
+    async fn tiles_at_the_edge_are_stored() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        migrate!().run(&pool).await.unwrap();
+        let style: TileStyle = "hsl-map".parse().unwrap();
+        let tile = Tile::new(0, 0, 1).validate().unwrap();
+        let upstream = UpstreamImg {
+            data: Bytes::from_static(b"jpg"),
+            content_type: String::from("image/jpeg"),
+            etag: None,
+            last_modified: None,
+        };
+        CachedImg::store(&pool, tile, &style, upstream)
+            .await
+            .unwrap();
+        let img = CachedImg::get(&pool, tile, &style).await.unwrap().unwrap();
+        assert_eq!(img.data.as_ref(), b"jpg");
+        let headers = img.headers(MAX_AGE).unwrap();
+        assert_eq!(
+            headers[0],
+            (header::CONTENT_TYPE, String::from("image/jpeg"))
+        );
+    }
+}
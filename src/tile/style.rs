@@ -0,0 +1,274 @@
+use super::Tile;
+use crate::conf::{DIGITRANSIT_IMG_URL, DIGITRANSIT_SV_IMG_URL, OSM_IMG_URL};
+use crate::err::{Error, Result};
+use crate::i18n::Lang;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Style of the map unless another one is configured
+const DEFAULT_STYLE: &str = "hsl-map";
+/// Digitransit basemap with the swedish names, used instead of the default one in swedish
+const SV_STYLE: &str = "hsl-map-sv";
+/// Style of the dark theme, if configured
+const DARK_STYLE: &str = "dark";
+/// Served when a tile cannot be fetched from its own source and it is not cached
+pub const FALLBACK_STYLE: &str = "osm";
+
+const DIGITRANSIT_ATTRIBUTION: &str = "© Digitransit © OpenStreetMap contributors";
+const OSM_ATTRIBUTION: &str = "© OpenStreetMap contributors";
+
+/// Name of a basemap style, eg. `hsl-map`. The images of each style are cached separately.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String")]
+pub struct TileStyle(Arc<str>);
+
+impl TileStyle {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for TileStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let valid = s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if s.is_empty() || s.len() > 32 || !valid {
+            return Err(format!("invalid style '{s}', expected a-z, 0-9 or -"));
+        }
+        Ok(Self(Arc::from(s)))
+    }
+}
+
+impl TryFrom<String> for TileStyle {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for TileStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Template of the image urls, `{z}`, `{x}` and `{y}` are replaced with the tile. The
+/// optional `{r}` is replaced with `@2x` for the high-dpi images, see [Tile::digitransit_url].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TileUrl(Arc<str>);
+
+impl TileUrl {
+    pub fn url(&self, tile: Tile) -> String {
+        tile.digitransit_url(&self.0)
+    }
+
+    /// Are there high-dpi images, otherwise the normal ones are served instead
+    pub fn has_scale(&self) -> bool {
+        self.0.contains("{r}")
+    }
+
+    /// The api key is only sent to digitransit
+    pub fn is_digitransit(&self) -> bool {
+        self.0.starts_with("https://cdn.digitransit.fi/")
+    }
+}
+
+impl FromStr for TileUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        if !s.starts_with("https://") && !s.starts_with("http://") {
+            return Err(String::from("expected an http(s) url"));
+        }
+        if let Some(missing) = ["{z}", "{x}", "{y}"].iter().find(|p| !s.contains(*p)) {
+            return Err(format!("{missing} missing"));
+        }
+        Ok(Self(Arc::from(s)))
+    }
+}
+
+impl TryFrom<String> for TileUrl {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for TileUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Where the images of a style come from, and the attribution shown on the map
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TileSource {
+    pub url: TileUrl,
+    pub attribution: String,
+}
+
+impl TileSource {
+    fn new(url: &str, attribution: &str) -> Self {
+        Self {
+            url: url.parse().expect("valid url"),
+            attribution: attribution.to_owned(),
+        }
+    }
+}
+
+/// The built-in styles (hsl-map, hsl-map-sv and osm) and the configured ones
+#[derive(Clone, Debug)]
+pub struct TileStyles {
+    sources: Arc<BTreeMap<TileStyle, TileSource>>,
+    default: TileStyle,
+}
+
+impl TileStyles {
+    /// The configured sources override the built-in ones with the same name
+    pub fn new(
+        configured: BTreeMap<TileStyle, TileSource>,
+        default: Option<TileStyle>,
+    ) -> std::result::Result<Self, String> {
+        let mut sources = BTreeMap::from([
+            (
+                style(DEFAULT_STYLE),
+                TileSource::new(DIGITRANSIT_IMG_URL, DIGITRANSIT_ATTRIBUTION),
+            ),
+            (
+                style(SV_STYLE),
+                TileSource::new(DIGITRANSIT_SV_IMG_URL, DIGITRANSIT_ATTRIBUTION),
+            ),
+            (
+                style(FALLBACK_STYLE),
+                TileSource::new(OSM_IMG_URL, OSM_ATTRIBUTION),
+            ),
+        ]);
+        sources.extend(configured);
+        let default = default.unwrap_or_else(|| style(DEFAULT_STYLE));
+        if !sources.contains_key(&default) {
+            return Err(format!("unknown style '{default}'"));
+        }
+        Ok(Self {
+            sources: Arc::new(sources),
+            default,
+        })
+    }
+
+    pub fn default_style(&self) -> &TileStyle {
+        &self.default
+    }
+
+    /// The requested style or the default one, errors if the style is not configured
+    pub fn get(&self, style: Option<TileStyle>) -> Result<(TileStyle, &TileSource)> {
+        let style = style.unwrap_or_else(|| self.default.clone());
+        match self.sources.get(&style) {
+            Some(source) => Ok((style, source)),
+            None => Err(Error::NotFound(format!("No map style '{style}'"))),
+        }
+    }
+
+    /// Style to try when the images of the given style cannot be fetched
+    pub fn fallback(&self, failed: &TileStyle) -> Option<TileStyle> {
+        let fallback = style(FALLBACK_STYLE);
+        (self.sources.contains_key(&fallback) && fallback != *failed).then_some(fallback)
+    }
+
+    /// The basemaps of a page in the given language. The swedish one replaces the default
+    /// style, and the dark theme uses the light basemap unless a dark style is configured.
+    pub fn for_page(&self, lang: Lang) -> MapStyles {
+        let sv = style(SV_STYLE);
+        let light = match lang {
+            Lang::Sv
+                if self.default.as_str() == DEFAULT_STYLE && self.sources.contains_key(&sv) =>
+            {
+                sv
+            }
+            _ => self.default.clone(),
+        };
+        let dark = Some(style(DARK_STYLE))
+            .filter(|dark| self.sources.contains_key(dark))
+            .unwrap_or_else(|| light.clone());
+        MapStyles {
+            light: self.basemap(light),
+            dark: self.basemap(dark),
+        }
+    }
+
+    fn basemap(&self, style: TileStyle) -> Basemap {
+        let attribution = self.sources[&style].attribution.clone();
+        Basemap { style, attribution }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &TileStyle> {
+        self.sources.keys()
+    }
+}
+
+fn style(name: &str) -> TileStyle {
+    name.parse().expect("valid style")
+}
+
+/// Style of a page along with its attribution
+#[derive(Clone, Debug)]
+pub struct Basemap {
+    pub style: TileStyle,
+    pub attribution: String,
+}
+
+/// The basemaps of a page in the light and dark themes, the browser switches between them
+#[derive(Clone, Debug)]
+pub struct MapStyles {
+    pub light: Basemap,
+    pub dark: Basemap,
+}
+
+impl MapStyles {
+    pub fn current(&self, dark: bool) -> &Basemap {
+        if dark { &self.dark } else { &self.light }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_url_templates_need_the_tile() {
+        let url: TileUrl = "https://maps.example/dark/{z}/{x}/{y}@2x.png"
+            .parse()
+            .unwrap();
+        let tile = Tile::new(3, 2, 4);
+        assert_eq!(url.url(tile), "https://maps.example/dark/4/3/2@2x.png");
+        assert!(!url.is_digitransit() && !url.has_scale());
+        assert!(
+            "https://maps.example/{z}/{x}.png"
+                .parse::<TileUrl>()
+                .is_err()
+        );
+        assert!("maps.example/{z}/{x}/{y}.png".parse::<TileUrl>().is_err());
+    }
+
+    #[test]
+    fn pages_use_the_swedish_and_dark_styles() {
+        let styles = TileStyles::new(BTreeMap::new(), None).unwrap();
+        let map = styles.for_page(Lang::Sv);
+        assert_eq!(map.light.style.as_str(), SV_STYLE);
+        assert_eq!(map.current(true).style.as_str(), SV_STYLE);
+        let dark = TileSource::new("https://maps.example/{z}/{x}/{y}.png", "© Example");
+        let styles = TileStyles::new(BTreeMap::from([(style(DARK_STYLE), dark)]), None).unwrap();
+        let map = styles.for_page(Lang::Fi);
+        assert_eq!(map.light.style.as_str(), DEFAULT_STYLE);
+        assert_eq!(map.current(true).attribution, "© Example");
+        assert!(TileStyles::new(BTreeMap::new(), Some(style("satellite"))).is_err());
+    }
+}
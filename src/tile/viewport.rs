@@ -0,0 +1,214 @@
+use super::{BBox, Tile, TileStyle, lat_y, lon_x, x_lon, y_lat};
+
+/// Circumference of the earth at the equator in meters
+const EQUATOR_M: f64 = 40_075_016.686;
+
+/// Square map view of `pixels` × `pixels` centered on a point. The coordinates are in tiles
+/// (at the zoom level of the view), so they are fractional unless the view is tile-aligned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    z: u8,
+    /// upper left corner
+    x: f64,
+    y: f64,
+    pixels: u16,
+}
+
+/// Image of a tile placed within the view, see [Viewport::imgs]
+pub struct TileImg {
+    pub path: String,
+    pub style: String,
+}
+
+/// Rectangle of tiles (the corners included) covering a view
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Tiles {
+    pub z: u8,
+    pub min_x: u32,
+    pub min_y: u32,
+    pub max_x: u32,
+    pub max_y: u32,
+}
+
+impl Viewport {
+    /// Width of the view in tiles, ie. the tiles are shown at `pixels / SPAN` pixels
+    pub const SPAN: f64 = 2.0;
+
+    /// View of the given size centered on the point
+    pub fn new(lon_deg: f64, lat_deg: f64, z: u8, pixels: u16) -> Self {
+        let n = 1 << z;
+        let half = Self::SPAN / 2.0;
+        Self {
+            z,
+            x: lon_x(n, lon_deg) - half,
+            y: lat_y(n, lat_deg) - half,
+            pixels,
+        }
+    }
+
+    /// Same view moved by the given number of tiles
+    pub fn moved(self, (dx, dy): (f64, f64)) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..self
+        }
+    }
+
+    pub fn z(&self) -> u8 {
+        self.z
+    }
+
+    pub fn x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn pixels(&self) -> u16 {
+        self.pixels
+    }
+
+    /// Size of a tile in pixels
+    pub fn tile_px(&self) -> f64 {
+        self.pixels as f64 / Self::SPAN
+    }
+
+    /// The tiles that are (at least partially) within the view, limited to the ones that exist
+    pub fn tiles(&self) -> Tiles {
+        let last = ((1u64 << self.z) - 1) as f64;
+        // the tiles that only touch the edge of the view are not needed
+        let min = |c: f64| c.floor().clamp(0.0, last) as u32;
+        let max = |c: f64| ((c + Self::SPAN).ceil() - 1.0).clamp(0.0, last) as u32;
+        Tiles {
+            z: self.z,
+            min_x: min(self.x),
+            min_y: min(self.y),
+            max_x: max(self.x),
+            max_y: max(self.y),
+        }
+    }
+
+    /// The images of the covering tiles, positioned relative to the upper left corner of the view
+    pub fn imgs(&self, style: &TileStyle, scale: u8) -> Vec<TileImg> {
+        let tiles = self.tiles();
+        let tile_px = self.tile_px();
+        let mut imgs = vec![];
+        for y in tiles.min_y..=tiles.max_y {
+            for x in tiles.min_x..=tiles.max_x {
+                let left = (x as f64 - self.x) * tile_px;
+                let top = (y as f64 - self.y) * tile_px;
+                let tile = Tile::new(x, y, self.z).scaled(scale);
+                imgs.push(TileImg {
+                    path: tile.img_path(style),
+                    style: format!("left: {left:.1}px; top: {top:.1}px; width: {tile_px:.1}px;"),
+                });
+            }
+        }
+        imgs
+    }
+
+    /// Coordinates relative to the upper left corner of the view in pixels
+    pub fn px_coord(&self, lon_deg: f64, lat_deg: f64) -> (f64, f64) {
+        let n = 1 << self.z;
+        let x = (lon_x(n, lon_deg) - self.x) * self.tile_px();
+        let y = (lat_y(n, lat_deg) - self.y) * self.tile_px();
+        (x, y)
+    }
+
+    /// Coordinates within the view in pixels, `None` if the point is outside the view
+    pub fn rel_coord(&self, lon_deg: f64, lat_deg: f64) -> Option<(u16, u16)> {
+        let px = self.pixels as f64;
+        let (x, y) = self.px_coord(lon_deg, lat_deg);
+        if x.min(y) < 0.0 || x.max(y) > px {
+            return None;
+        }
+        Some((x.round() as u16, y.round() as u16))
+    }
+
+    /// Length in pixels of a distance in meters at the given latitude
+    pub fn m_to_px(&self, lat_deg: f64, meters: f64) -> f64 {
+        let n = (1u64 << self.z) as f64;
+        let tile_m = EQUATOR_M * lat_deg.to_radians().cos() / n;
+        meters / tile_m * self.tile_px()
+    }
+}
+
+impl Tiles {
+    /// Area covered by the tiles
+    pub fn bbox(&self) -> BBox {
+        let n = 1 << self.z;
+        BBox {
+            min_lon: x_lon(n, self.min_x as f64),
+            min_lat: y_lat(n, (self.max_y + 1) as f64),
+            max_lon: x_lon(n, (self.max_x + 1) as f64),
+            max_lat: y_lat(n, self.min_y as f64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn center_is_in_the_middle() {
+        let view = Viewport::new(24.94, 60.17, 15, 350);
+        assert_eq!(view.rel_coord(24.94, 60.17), Some((175, 175)));
+        let moved = view.moved((0.5, -0.25));
+        assert_eq!(moved.rel_coord(24.94, 60.17), Some((88, 219)));
+    }
+
+    #[test]
+    fn tiles_cover_the_view() {
+        let view = Viewport::new(24.94, 60.17, 15, 350);
+        let tiles = view.tiles();
+        assert_eq!(
+            (tiles.max_x - tiles.min_x, tiles.max_y - tiles.min_y),
+            (2, 2)
+        );
+        let bbox = tiles.bbox();
+        for (lon, lat) in [(bbox.min_lon, bbox.max_lat), (bbox.max_lon, bbox.min_lat)] {
+            let (x, y) = (lon_x(1 << 15, lon), lat_y(1 << 15, lat));
+            assert!(x <= view.x || x >= view.x + Viewport::SPAN);
+            assert!(y <= view.y || y >= view.y + Viewport::SPAN);
+        }
+        // tile-aligned view
+        let aligned = Viewport {
+            x: 10.0,
+            y: 20.0,
+            ..view
+        };
+        let tiles = aligned.tiles();
+        assert_eq!(
+            (tiles.min_x, tiles.max_x, tiles.min_y, tiles.max_y),
+            (10, 11, 20, 21)
+        );
+    }
+
+    #[test]
+    fn bbox_corners_are_view_corners() {
+        // the tiles of a tile-aligned view cover exactly the view
+        let view = Viewport::new(24.94, 60.17, 15, 350);
+        let view = view.moved((view.x.round() - view.x, view.y.round() - view.y));
+        let bbox = view.tiles().bbox();
+        assert_eq!(view.rel_coord(bbox.min_lon, bbox.max_lat), Some((0, 0)));
+        assert_eq!(view.rel_coord(bbox.max_lon, bbox.min_lat), Some((350, 350)));
+        assert_eq!(view.rel_coord(bbox.max_lon + 0.001, bbox.min_lat), None);
+        // otherwise they cover more than the view
+        let view = view.moved((0.3, 0.6));
+        let bbox = view.tiles().bbox();
+        let n = 1 << view.z;
+        for (x, y) in [
+            (view.x, view.y),
+            (view.x + Viewport::SPAN, view.y + Viewport::SPAN),
+        ] {
+            let (lon, lat) = (x_lon(n, x), y_lat(n, y));
+            assert!((bbox.min_lon..=bbox.max_lon).contains(&lon));
+            assert!((bbox.min_lat..=bbox.max_lat).contains(&lat));
+            assert!(view.rel_coord(lon, lat).is_some());
+        }
+    }
+}
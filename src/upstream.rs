@@ -0,0 +1,204 @@
+use crate::err::{Error, Result};
+use crate::metrics::{Api, metrics};
+use reqwest::{Client, IntoUrl, RequestBuilder, Response, StatusCode, Url, header};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+/// Header (or query parameter) for the api key
+const API_KEY: &str = "digitransit-subscription-key";
+
+/// Timeout for a single request (including reading the body) to the digitransit apis
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+/// How many times a failed request is retried
+pub const RETRIES: u32 = 2;
+/// Delay before the first retry, doubled after each retry
+const BACKOFF: Duration = Duration::from_millis(250);
+/// Limit for all the attempts together, so that the pages fall back to stale data quickly
+const TOTAL_TIMEOUT: Duration = Duration::from_secs(6);
+/// Calls slower than this are logged as warnings
+const SLOW: Duration = Duration::from_secs(1);
+
+/// Unix times of the latest successful and failed calls (after retries), 0 if none
+static LAST_OK: AtomicI64 = AtomicI64::new(0);
+static LAST_ERR: AtomicI64 = AtomicI64::new(0);
+
+/// Client for the digitransit apis. Cheap to clone, the connection pool is shared.
+#[derive(Clone)]
+pub struct Digitransit {
+    client: Client,
+    api_key: Arc<str>,
+}
+
+impl Digitransit {
+    pub fn new(api_key: String) -> Result<Self> {
+        // the osm tile servers require an identifying user agent
+        let client = Client::builder()
+            .connect_timeout(TIMEOUT)
+            .user_agent(concat!("bikes/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Self {
+            client,
+            api_key: api_key.into(),
+        })
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url).header(API_KEY, self.api_key.as_ref())
+    }
+
+    /// Request to a third party, eg. a tile server, that must not get the api key
+    pub fn get_public(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.post(url).header(API_KEY, self.api_key.as_ref())
+    }
+}
+
+impl fmt::Debug for Digitransit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Digitransit")
+            .field("client", &self.client)
+            .field("api_key", &"REDACTED")
+            .finish()
+    }
+}
+
+/// Sends the request with a timeout. Timeouts, connection errors and 429/5xx-responses are
+/// retried with exponential backoff (within [TOTAL_TIMEOUT]), so this must only be used for
+/// idempotent requests.
+/// Statuses other than success or 304 Not Modified (for conditional requests) are returned as errors.
+/// Each attempt is recorded in the metrics of the given api.
+pub async fn send(api: Api, req: RequestBuilder) -> Result<Response> {
+    let url = req
+        .try_clone()
+        .and_then(|req| req.build().ok())
+        .map(|req| redact(req.url()))
+        .unwrap_or_default();
+    let span = tracing::info_span!("upstream", api = api.as_str(), url);
+    let retries = send_with_retries(api, req, &url).instrument(span);
+    let res = match tokio::time::timeout(TOTAL_TIMEOUT, retries).await {
+        Ok(res) => res,
+        Err(_) => Err(Error::UpstreamTimeout(format!(
+            "{url}: no response in {}s",
+            TOTAL_TIMEOUT.as_secs()
+        ))),
+    };
+    let last = if res.is_ok() { &LAST_OK } else { &LAST_ERR };
+    last.store(jiff::Timestamp::now().as_second(), Ordering::Relaxed);
+    res
+}
+
+/// Unix times of the latest successful and failed calls to any of the apis
+pub fn last_calls() -> (Option<i64>, Option<i64>) {
+    let get = |last: &AtomicI64| Some(last.load(Ordering::Relaxed)).filter(|&t| t > 0);
+    (get(&LAST_OK), get(&LAST_ERR))
+}
+
+async fn send_with_retries(api: Api, req: RequestBuilder, url: &str) -> Result<Response> {
+    let mut delay = BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let req = req
+            .try_clone()
+            .ok_or("streaming request bodies cannot be retried")?
+            .timeout(TIMEOUT);
+        let start = Instant::now();
+        // reqwest errors contain the full url
+        let res = req.send().await.map_err(|e| e.without_url());
+        let latency = start.elapsed();
+        let ok = res.as_ref().is_ok_and(|r| is_ok(r.status()));
+        metrics().observe_upstream(api, ok, latency);
+        if latency > SLOW {
+            tracing::warn!(?latency, "slow upstream call");
+        } else {
+            tracing::debug!(?latency, ok);
+        }
+        let retryable = match &res {
+            Ok(resp) => is_retryable(resp.status()),
+            Err(e) => e.is_timeout() || e.is_connect(),
+        };
+        if retryable && attempt < RETRIES {
+            match &res {
+                Ok(resp) => tracing::warn!("{url} returned {}, retrying", resp.status()),
+                Err(e) => tracing::warn!("{url}: {e}, retrying"),
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+            continue;
+        }
+        let resp = res.map_err(|e| with_url(e, url))?;
+        if !is_ok(resp.status()) {
+            return Err(Error::Upstream(format!("{url} returned {}", resp.status())));
+        }
+        return Ok(resp);
+    }
+}
+
+/// Url for logging, without the api key (in case it is passed as a query parameter)
+pub fn redact(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.query_pairs().any(|(k, _)| k == API_KEY) {
+        let pairs: Vec<_> = url
+            .query_pairs()
+            .map(|(k, v)| {
+                let v = if k == API_KEY { "REDACTED".into() } else { v };
+                (k.into_owned(), v.into_owned())
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+fn with_url(e: reqwest::Error, url: &str) -> Error {
+    let msg = format!("{url}: {e}");
+    if e.is_timeout() {
+        Error::UpstreamTimeout(msg)
+    } else {
+        Error::Upstream(msg)
+    }
+}
+
+fn is_ok(status: StatusCode) -> bool {
+    status.is_success() || status == StatusCode::NOT_MODIFIED
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Make sure that the response has the expected content-type (eg. `image/`) before it is used
+pub fn check_content_type(resp: &Response, expected: &str) -> Result<()> {
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.starts_with(expected) {
+        return Err(Error::Upstream(format!(
+            "{} returned content-type '{content_type}', expected '{expected}'",
+            redact(resp.url())
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_key_is_redacted() {
+        let url =
+            Url::parse("https://example.com/a?x=1&digitransit-subscription-key=secret").unwrap();
+        let redacted = redact(&url);
+        assert!(!redacted.contains("secret"));
+        assert!(redacted.contains("x=1"));
+    }
+}
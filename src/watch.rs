@@ -0,0 +1,294 @@
+use crate::err::{Error, Result};
+use crate::err_to_resp;
+use crate::i18n::Lang;
+use crate::server::AppState;
+use crate::station::{Group, StationData, StationObs};
+use crate::upstream::Digitransit;
+use axum::Json;
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+pub use poller::poll_watches;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{SqlitePool, query};
+use webhook::Webhook;
+
+mod poller;
+mod webhook;
+
+/// Watches expire after this many minutes by default
+const DEFAULT_MINUTES: u32 = 2 * 60;
+/// and at most after this many minutes
+const MAX_MINUTES: u32 = 7 * 24 * 60;
+
+/// What is being watched, either a single station or any station near a station group
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Station(String),
+    Group(String),
+}
+
+/// Are we waiting for bikes or free docks
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum WatchKind {
+    Bikes,
+    Docks,
+}
+
+impl WatchKind {
+    fn value(&self, station: &StationObs) -> u16 {
+        match self {
+            WatchKind::Bikes => station.count,
+            WatchKind::Docks => station.spaces,
+        }
+    }
+
+    fn describe(&self, n: u16) -> String {
+        match (self, n) {
+            (WatchKind::Bikes, 1) => String::from("1 bike"),
+            (WatchKind::Bikes, n) => format!("{n} bikes"),
+            (WatchKind::Docks, 1) => String::from("1 free dock"),
+            (WatchKind::Docks, n) => format!("{n} free docks"),
+        }
+    }
+}
+
+/// Fires the webhook when the target has at least `threshold` bikes or free docks
+#[derive(Debug, Serialize)]
+pub struct Watch {
+    id: i64,
+    #[serde(flatten)]
+    target: Target,
+    kind: WatchKind,
+    threshold: u16,
+    webhook: String,
+    expires: i64,
+    /// was the watch satisfied the last time it was checked
+    #[serde(skip)]
+    satisfied: bool,
+    /// when the webhook was last fired
+    #[serde(skip)]
+    fired: Option<i64>,
+}
+
+impl Watch {
+    fn is_satisfied_by(&self, station: &StationObs) -> bool {
+        self.kind.value(station) >= self.threshold
+    }
+
+    fn message(&self, station: &StationObs) -> String {
+        let n = self.kind.value(station);
+        format!(
+            "{} {} has {}",
+            station.id,
+            station.name,
+            self.kind.describe(n)
+        )
+    }
+
+    /// List all watches
+    pub async fn get_all(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let rows = query!(
+            r#"
+            SELECT id, station_id, group_name, kind AS "kind: WatchKind",
+              threshold AS "threshold: u16", webhook, expires, satisfied AS "satisfied: bool", fired
+              FROM watch ORDER BY id ASC
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+        rows.into_iter()
+            .map(|r| {
+                let target = match (r.station_id, r.group_name) {
+                    (Some(id), _) => Target::Station(id),
+                    (None, Some(name)) => Target::Group(name),
+                    (None, None) => {
+                        return Err(Error::from(format!("watch {} has no target", r.id)));
+                    }
+                };
+                Ok(Self {
+                    id: r.id,
+                    target,
+                    kind: r.kind,
+                    threshold: r.threshold,
+                    webhook: r.webhook,
+                    expires: r.expires,
+                    satisfied: r.satisfied,
+                    fired: r.fired,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove the watch, returns false if it did not exist
+    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<bool> {
+        let res = query!(r#"DELETE FROM watch WHERE id = ?"#, id)
+            .execute(pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Remove the watches that have expired
+    async fn delete_expired(pool: &SqlitePool, now: i64) -> Result<()> {
+        query!(r#"DELETE FROM watch WHERE expires <= ?"#, now)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_state(
+        &self,
+        pool: &SqlitePool,
+        satisfied: bool,
+        fired: Option<i64>,
+    ) -> Result<()> {
+        query!(
+            r#"UPDATE watch SET satisfied = ?, fired = COALESCE(?, fired) WHERE id = ?"#,
+            satisfied,
+            fired,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Body for creating a watch, exactly one of `station` and `group` must be given
+#[derive(Debug, Deserialize)]
+pub struct NewWatch {
+    station: Option<String>,
+    group: Option<String>,
+    kind: WatchKind,
+    threshold: u16,
+    /// name of a webhook in the db
+    webhook: String,
+    /// minutes until the watch expires
+    minutes: Option<u32>,
+}
+
+impl NewWatch {
+    async fn insert(self, pool: &SqlitePool, digitransit: &Digitransit) -> Result<Watch> {
+        let target = match (self.station, self.group) {
+            // a watch for a station that does not exist would never fire
+            (Some(id), None) => {
+                match StationData::get_by_id(digitransit, &id, Lang::default()).await? {
+                    Some(_) => Target::Station(id),
+                    None => return Err(Error::BadRequest(format!("no station with id '{id}'"))),
+                }
+            }
+            (None, Some(name)) => {
+                Target::Group(Group::get_with_name(pool, &name).await?.name().to_owned())
+            }
+            _ => {
+                return Err(Error::BadRequest(String::from(
+                    "exactly one of station and group is required",
+                )));
+            }
+        };
+        if !(1..=100).contains(&self.threshold) {
+            return Err(Error::BadRequest(String::from(
+                "threshold must be between 1 and 100",
+            )));
+        }
+        let minutes = self.minutes.unwrap_or(DEFAULT_MINUTES);
+        if !(1..=MAX_MINUTES).contains(&minutes) {
+            return Err(Error::BadRequest(format!(
+                "minutes must be between 1 and {MAX_MINUTES}"
+            )));
+        }
+        if Webhook::get(pool, &self.webhook).await?.is_none() {
+            return Err(Error::BadRequest(format!(
+                "no webhook named '{}'",
+                self.webhook
+            )));
+        }
+
+        let expires = jiff::Timestamp::now().as_second() + i64::from(minutes) * 60;
+        let (station_id, group_name) = match &target {
+            Target::Station(id) => (Some(id), None),
+            Target::Group(name) => (None, Some(name)),
+        };
+        let row = query!(
+            r#"
+            INSERT INTO watch (station_id, group_name, kind, threshold, webhook, expires)
+              VALUES (?, ?, ?, ?, ?, ?)
+              RETURNING id AS "id!"
+            "#,
+            station_id,
+            group_name,
+            self.kind,
+            self.threshold,
+            self.webhook,
+            expires
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(Watch {
+            id: row.id,
+            target,
+            kind: self.kind,
+            threshold: self.threshold,
+            webhook: self.webhook,
+            expires,
+            satisfied: false,
+            fired: None,
+        })
+    }
+}
+
+/// Middleware for the watch api, which requires `Authorization: Bearer <watch_token>`.
+/// Without a configured token the api does not exist.
+pub async fn require_watch_token(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = state.watch_token.as_deref() else {
+        return Error::NotFound(String::from("Watches are not enabled")).into_response();
+    };
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    // the digests are compared so that the time does not depend on the matching prefix
+    let digest = |t: &str| Sha256::digest(t.trim().as_bytes());
+    if given.is_none_or(|given| digest(given) != digest(token)) {
+        return Error::Unauthorized(String::from("Invalid or missing watch token")).into_response();
+    }
+    next.run(req).await
+}
+
+/// List the watches as json
+pub async fn get_watches(State(pool): State<SqlitePool>) -> Response {
+    Json(err_to_resp!(Watch::get_all(&pool).await)).into_response()
+}
+
+/// Create a new watch
+pub async fn post_watch(
+    State(state): State<AppState>,
+    new_watch: std::result::Result<Json<NewWatch>, JsonRejection>,
+) -> Response {
+    let Json(new_watch) = err_to_resp!(new_watch);
+    let watch = err_to_resp!(new_watch.insert(&state.pool, &state.digitransit).await);
+    (StatusCode::CREATED, Json(watch)).into_response()
+}
+
+/// Remove a watch
+pub async fn delete_watch(
+    State(pool): State<SqlitePool>,
+    id: std::result::Result<Path<i64>, PathRejection>,
+) -> Response {
+    let Path(id) = err_to_resp!(id);
+    if !err_to_resp!(Watch::delete(&pool, id).await) {
+        return Error::NotFound(format!("No watch with id {id}")).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
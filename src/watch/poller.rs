@@ -0,0 +1,204 @@
+use super::webhook::{Notification, Webhook};
+use super::{Target, Watch};
+use crate::err::Result;
+use crate::i18n::Lang;
+use crate::server::AppState;
+use crate::station::{Group, StationData, StationObs, StationQuery};
+use crate::upstream;
+use reqwest::Client;
+use std::time::Duration;
+
+/// How often the watches are checked
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Minimum time (in seconds) between two notifications from the same watch, so that
+/// a count going back and forth around the threshold does not flood the webhook
+const DEBOUNCE: i64 = 15 * 60;
+/// Group watches are satisfied by any station within this distance (m) from the group
+const GROUP_RADIUS: u16 = 500;
+
+/// What to do with a watch after checking it
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// nothing changed
+    Keep,
+    /// the watch is not satisfied anymore
+    Reset,
+    /// the watch became satisfied, but it was fired too recently
+    Debounce,
+    /// the watch became satisfied, fire the webhook
+    Fire,
+}
+
+/// Station satisfying a watch, if it is known
+#[derive(Debug)]
+enum Match {
+    Found(StationObs),
+    NotFound,
+    /// the data is stale, the state of the watch is kept until there is fresh data
+    Unknown,
+}
+
+impl From<Option<StationObs>> for Match {
+    fn from(station: Option<StationObs>) -> Self {
+        station.map_or(Match::NotFound, Match::Found)
+    }
+}
+
+impl Watch {
+    /// Only the change to satisfied fires the webhook, and at most once in [DEBOUNCE]
+    fn step(&self, satisfied: bool, now: i64) -> Step {
+        match (self.satisfied, satisfied) {
+            (true, false) => Step::Reset,
+            (false, true) if self.fired.is_some_and(|fired| now - fired < DEBOUNCE) => {
+                Step::Debounce
+            }
+            (false, true) => Step::Fire,
+            _ => Step::Keep,
+        }
+    }
+}
+
+/// Background task that checks the watches and fires the webhooks
+pub async fn poll_watches(state: AppState) {
+    let client = match Client::builder().timeout(upstream::TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("watches disabled: {e}");
+            return;
+        }
+    };
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = check_watches(&state, &client).await {
+            tracing::error!("checking watches failed: {e}");
+        }
+    }
+}
+
+async fn check_watches(state: &AppState, client: &Client) -> Result<()> {
+    let now = jiff::Timestamp::now().as_second();
+    Watch::delete_expired(&state.pool, now).await?;
+    let watches = Watch::get_all(&state.pool).await?;
+
+    let mut ids: Vec<_> = watches
+        .iter()
+        .filter_map(|w| match &w.target {
+            Target::Station(id) => Some(id.as_str()),
+            Target::Group(_) => None,
+        })
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let stations = match ids.is_empty() {
+        true => StationData::from(vec![]),
+        false => StationData::get_by_ids(&state.digitransit, &ids, Lang::default()).await?,
+    };
+
+    for watch in &watches {
+        if let Err(e) = check_watch(state, client, watch, &stations, now).await {
+            tracing::error!("checking watch {} failed: {e}", watch.id);
+        }
+    }
+    Ok(())
+}
+
+async fn check_watch(
+    state: &AppState,
+    client: &Client,
+    watch: &Watch,
+    stations: &StationData,
+    now: i64,
+) -> Result<()> {
+    let matching = match &watch.target {
+        Target::Station(id) => Match::from(
+            stations
+                .find(id)
+                .filter(|s| watch.is_satisfied_by(s))
+                .cloned(),
+        ),
+        Target::Group(name) => group_match(state, watch, name).await?,
+    };
+    let matching = match matching {
+        Match::Found(station) => Some(station),
+        Match::NotFound => None,
+        Match::Unknown => return Ok(()),
+    };
+    let station = match (watch.step(matching.is_some(), now), matching) {
+        (Step::Reset, _) => return watch.update_state(&state.pool, false, None).await,
+        (Step::Debounce, _) => return watch.update_state(&state.pool, true, None).await,
+        (Step::Fire, Some(station)) => station,
+        _ => return Ok(()),
+    };
+
+    let webhook = Webhook::get(&state.pool, &watch.webhook).await?;
+    let Some(webhook) = webhook else {
+        return Err(format!("webhook '{}' does not exist", watch.webhook).into());
+    };
+    let notification = Notification {
+        watch: watch.id,
+        station: &station,
+        message: watch.message(&station),
+    };
+    webhook.fire(client, &notification).await?;
+    tracing::info!("watch {}: {}", watch.id, notification.message);
+    watch.update_state(&state.pool, true, Some(now)).await
+}
+
+/// Nearest station (close to the group) that satisfies the watch, unknown if the data is stale
+async fn group_match(state: &AppState, watch: &Watch, name: &str) -> Result<Match> {
+    let (lon, lat) = Group::get_with_name(&state.pool, name).await?.lon_lat();
+    let q = StationQuery::new(lon, lat, GROUP_RADIUS, 10, Lang::default());
+    let snapshot = state
+        .stations
+        .get(&state.pool, &state.digitransit, q)
+        .await?;
+    if snapshot.stale {
+        return Ok(Match::Unknown);
+    }
+    let station = snapshot
+        .data
+        .observations()
+        .iter()
+        .find(|s| watch.is_satisfied_by(s));
+    Ok(Match::from(station.cloned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::WatchKind;
+    use super::*;
+
+    fn watch(satisfied: bool, fired: Option<i64>) -> Watch {
+        Watch {
+            id: 1,
+            target: Target::Station(String::from("001")),
+            kind: WatchKind::Bikes,
+            threshold: 2,
+            webhook: String::from("phone"),
+            expires: 10_000,
+            satisfied,
+            fired,
+        }
+    }
+
+    #[test]
+    fn only_becoming_satisfied_fires() {
+        assert_eq!(watch(false, None).step(true, 1000), Step::Fire);
+        assert_eq!(watch(true, Some(1000)).step(true, 1060), Step::Keep);
+        assert_eq!(watch(true, Some(1000)).step(false, 1120), Step::Reset);
+        assert_eq!(watch(false, None).step(false, 1000), Step::Keep);
+    }
+
+    #[test]
+    fn firing_again_is_debounced() {
+        let fired = Some(1000);
+        assert_eq!(
+            watch(false, fired).step(true, 1000 + DEBOUNCE - 1),
+            Step::Debounce
+        );
+        // the debounced change is stored as satisfied, so it does not fire later either
+        assert_eq!(watch(true, fired).step(true, 1000 + DEBOUNCE), Step::Keep);
+        assert_eq!(watch(false, fired).step(true, 1000 + DEBOUNCE), Step::Fire);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::err::{Error, Result};
+use crate::station::StationObs;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{SqlitePool, query_as};
+
+/// How the notification is sent
+#[derive(Clone, Copy, Debug, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// POST the [Notification] as json
+    Json,
+    /// POST the message as plain text, eg. to https://ntfy.sh/some-topic
+    Ntfy,
+    /// send the message as `m.text` to a matrix room, the url is the room endpoint
+    /// (`https://{server}/_matrix/client/v3/rooms/{room_id}`)
+    Matrix,
+}
+
+/// Webhook configured in the db, the token (if any) is sent as a bearer token
+pub struct Webhook {
+    name: String,
+    format: WebhookFormat,
+    url: String,
+    token: Option<String>,
+}
+
+/// Body of the json webhook
+#[derive(Serialize)]
+pub struct Notification<'a> {
+    pub watch: i64,
+    pub station: &'a StationObs,
+    pub message: String,
+}
+
+impl Webhook {
+    pub async fn get(pool: &SqlitePool, name: &str) -> Result<Option<Self>> {
+        let row = query_as!(
+            Self,
+            r#"SELECT name, format AS "format: WebhookFormat", url, token FROM webhook WHERE name = ?"#,
+            name
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(row)
+    }
+
+    /// Send the notification. Failures are not retried here, the watch is just checked again
+    /// on the next poll.
+    pub async fn fire(&self, client: &Client, notification: &Notification<'_>) -> Result<()> {
+        let req = match self.format {
+            WebhookFormat::Json => client.post(&self.url).json(notification),
+            WebhookFormat::Ntfy => client
+                .post(&self.url)
+                .header("Title", "bikes")
+                .header("Tags", "bike")
+                .body(notification.message.clone()),
+            WebhookFormat::Matrix => {
+                let txn_id = format!(
+                    "bikes-{}-{}",
+                    notification.watch,
+                    jiff::Timestamp::now().as_millisecond()
+                );
+                let url = format!(
+                    "{}/send/m.room.message/{txn_id}",
+                    self.url.trim_end_matches('/')
+                );
+                let body = json!({ "msgtype": "m.text", "body": notification.message });
+                client.put(url).json(&body)
+            }
+        };
+        let req = match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        };
+        let resp = req.send().await?;
+        if !resp.status().is_success() {
+            return Err(Error::Upstream(format!(
+                "webhook '{}' returned {}",
+                self.name,
+                resp.status()
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+// the ids of the starred stations are kept in a cookie so that the server can render them
+const favoritesAge = 365 * 24 * 60 * 60;
+const maxFavorites = 20;
+
+function readFavorites() {
+  const cookie = document.cookie.split(';').map((c) => c.trim()).find((c) => c.startsWith('favorites='));
+  if (!cookie) return [];
+  return cookie.slice('favorites='.length).split('.').filter((id) => id);
+}
+
+function writeFavorites(ids) {
+  document.cookie = `favorites=${ids.join('.')}; Path=/; Max-Age=${favoritesAge}; SameSite=Lax`;
+}
+
+function toggle(button) {
+  const id = button.dataset.id;
+  const ids = readFavorites().filter((fav) => fav !== id);
+  const starred = button.getAttribute('aria-pressed') !== 'true';
+  if (starred) {
+    if (ids.length >= maxFavorites) return;
+    ids.push(id);
+  }
+  writeFavorites(ids);
+  button.setAttribute('aria-pressed', starred);
+  button.textContent = starred ? '★' : '☆';
+  button.title = starred ? button.dataset.unstar : button.dataset.star;
+}
+
+// the rows of the stations are replaced when the map is panned
+document.addEventListener('click', (event) => {
+  const button = event.target.closest('.star');
+  if (button) toggle(button);
+});
@@ -0,0 +1,40 @@
+// moves the "you are here" marker as the position changes, the stations are not refetched.
+// The tile math is in move.js.
+
+const equatorM = 40075016.686;
+
+// same as Viewport::m_to_px
+function metersToPx(view, lat, meters) {
+  const tileM = (equatorM * Math.cos((lat / 180) * Math.PI)) / 2 ** view.z;
+  return (meters / tileM) * view.tilePx;
+}
+
+function updateHere(container, here, accuracy, coords) {
+  // the view may have been panned since the page was loaded
+  const view = readView(container);
+  placeLonLat(view, here, coords.longitude, coords.latitude);
+  placeLonLat(view, accuracy, coords.longitude, coords.latitude);
+  accuracy.hidden = !coords.accuracy;
+  const d = 2 * Math.min(Math.round(metersToPx(view, coords.latitude, coords.accuracy)), view.pixels);
+  accuracy.style.width = `${d}px`;
+  accuracy.style.height = `${d}px`;
+  // the heading is only known when moving
+  const moving = Number.isFinite(coords.heading) && coords.speed > 0;
+  here.classList.toggle('heading', moving);
+  if (moving) here.style.setProperty('--heading', `${coords.heading}deg`);
+}
+
+async function watchHere() {
+  const container = document.querySelector('.img-container');
+  const here = container && container.querySelector('.here');
+  if (!here || !navigator.geolocation) return;
+  // do not ask for the permission, the page was opened with a known position
+  if (navigator.permissions) {
+    const status = await navigator.permissions.query({ name: 'geolocation' });
+    if (status.state !== 'granted') return;
+  }
+  const accuracy = container.querySelector('.accuracy');
+  navigator.geolocation.watchPosition((pos) => updateHere(container, here, accuracy, pos.coords));
+}
+
+window.addEventListener('load', watchHere);
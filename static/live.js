@@ -0,0 +1,59 @@
+const countClasses = ['empty', 'low', 'mid', 'high'];
+
+// same thresholds as in Station::count_class
+function countClass(count) {
+  if (count === 0) return 'empty';
+  if (count < 3) return 'low';
+  if (count < 6) return 'mid';
+  return 'high';
+}
+
+// translated "{} bike(s)" from the page
+function bikes(main, count) {
+  const text = count === 1 ? main.dataset.bikesOne : main.dataset.bikesOther;
+  return text.replace('{}', count);
+}
+
+// translated status of the count, so that it is not shown only by the colour
+function countStatus(main, count) {
+  const cls = countClass(count);
+  return main.dataset[`status${cls[0].toUpperCase()}${cls.slice(1)}`];
+}
+
+// same as the aria-label of the pins in imgs.html
+function pinLabel(main, pin, count) {
+  return `${pin.dataset.station} ${pin.dataset.name}: ${bikes(main, count)}, ${countStatus(main, count)}`;
+}
+
+function updateStations(main, snapshot) {
+  for (const station of snapshot.stations) {
+    document.querySelectorAll(`[data-station="${station.id}"]`).forEach((elem) => {
+      elem.classList.remove(...countClasses);
+      elem.classList.add(countClass(station.count));
+      const count = elem.querySelector('.count');
+      if (count) count.textContent = bikes(main, station.count);
+      const status = elem.querySelector('.status');
+      if (status) status.textContent = countStatus(main, station.count);
+      if (elem.classList.contains('pin')) elem.setAttribute('aria-label', pinLabel(main, elem, station.count));
+    });
+  }
+  const stale = document.querySelector('.stale');
+  if (stale && !snapshot.stale) stale.remove();
+}
+
+let liveSource = null;
+
+// (re)connect to the updates of the stations in the view, see move.js
+function startLive(main) {
+  if (!window.EventSource) return;
+  if (liveSource) liveSource.close();
+  liveSource = new EventSource(main.dataset.live);
+  liveSource.onmessage = (event) => updateStations(main, JSON.parse(event.data));
+}
+
+function live() {
+  const main = document.querySelector('main[data-live]');
+  if (main) startLive(main);
+}
+
+window.addEventListener('load', live);
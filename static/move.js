@@ -1,33 +1,313 @@
-function handleSwipe(elem) {
-  elem.addEventListener("touchstart", (event) => {
-    const touch = event.changedTouches[0];
-    localStorage.setItem('start-id', touch.identifier);
-    localStorage.setItem('start-x', touch.clientX);
-    localStorage.setItem('start-y', touch.clientY);
-  });
-
-  elem.addEventListener("touchmove", (event) => event.preventDefault());
-
-  elem.addEventListener("touchend", (event) => {
-    const touch = event.changedTouches[0];
-    if (String(touch.identifier) === localStorage.getItem('start-id')) {
-      const dx = touch.clientX - Number(localStorage.getItem('start-x'));
-      const dy = touch.clientY - Number(localStorage.getItem('start-y'));
-      if (Math.max(Math.abs(dx), Math.abs(dy)) < 20) return;
-
-      const params = new URLSearchParams(window.location.search);
-      if (Math.abs(dx) > Math.abs(dy)) {
-        params.set('dx', Number(params.get('dx')) - Math.sign(dx))
-      } else {
-        params.set('dy', Number(params.get('dy')) - Math.sign(dy))
-      }
-      window.location.search = params.toString();
+// panning the map view without reloading the page, the coordinates are in tiles as in
+// Viewport (viewport.rs)
+
+const maxDelta = 20;
+// how far the arrow keys move the view, in tiles
+const keyStep = 0.25;
+// the stations are fetched once the view has stopped moving for this long (ms)
+const refreshDelay = 300;
+// range of the size of the view in pixels, same as in station.rs
+const minPixels = 200;
+const maxPixels = 800;
+
+// same as lon_x and lat_y in tile.rs
+function lonX(n, lon) {
+  return ((lon + 180) / 360) * n;
+}
+
+function latY(n, lat) {
+  const latRad = (lat / 180) * Math.PI;
+  return ((1 - Math.asinh(Math.tan(latRad)) / Math.PI) / 2) * n;
+}
+
+function readView(container) {
+  const data = container.dataset;
+  return {
+    z: Number(data.z),
+    x: Number(data.x),
+    y: Number(data.y),
+    tilePx: Number(data.tilePx),
+    pixels: Number(data.pixels),
+  };
+}
+
+// same as Viewport::rel_coord, null if the point is outside the view
+function relCoord(view, lon, lat) {
+  const n = 2 ** view.z;
+  const x = (lonX(n, lon) - view.x) * view.tilePx;
+  const y = (latY(n, lat) - view.y) * view.tilePx;
+  if (Math.min(x, y) < 0 || Math.max(x, y) > view.pixels) return null;
+  return { x: Math.round(x), y: Math.round(y) };
+}
+
+// the pins and the markers remember their position in tiles so that they move with the view
+function placeInView(view, elem, tileX, tileY) {
+  elem.dataset.tileX = tileX;
+  elem.dataset.tileY = tileY;
+  elem.style.left = `${((tileX - view.x) * view.tilePx).toFixed(1)}px`;
+  elem.style.top = `${((tileY - view.y) * view.tilePx).toFixed(1)}px`;
+}
+
+function placeLonLat(view, elem, lon, lat) {
+  const n = 2 ** view.z;
+  placeInView(view, elem, lonX(n, lon), latY(n, lat));
+}
+
+// hide the broken image icons for tiles that could not be loaded
+function markMissingTiles(elem) {
+  elem.querySelectorAll('img').forEach((img) => {
+    if (img.complete && img.naturalWidth === 0) img.classList.add('missing');
+    img.addEventListener('error', () => img.classList.add('missing'));
+  });
+}
+
+// same as Dpr::scale_at, the high-dpi images are also used if the tiles are shown larger
+// than their normal size
+function tileScale(view) {
+  return (window.devicePixelRatio || 1) * (view.tilePx / 256) > 1.2 ? 2 : 1;
+}
+
+function setScale(url, scale) {
+  if (scale === 1) url.searchParams.delete('dpr');
+  else url.searchParams.set('dpr', scale);
+  return url;
+}
+
+function tileImg(container, view, x, y) {
+  const img = document.createElement('img');
+  const url = new URL(`/img?z=${view.z}&x=${x}&y=${y}`, window.location);
+  url.searchParams.set('style', container.dataset.tileStyle);
+  img.src = setScale(url, tileScale(view));
+  // the region is labelled, the tiles themselves are decorative
+  img.alt = '';
+  img.draggable = false;
+  img.addEventListener('error', () => img.classList.add('missing'));
+  return img;
+}
+
+// same tiles as Viewport::tiles, the ones already loaded are reused
+function layoutTiles(container, view) {
+  const last = 2 ** view.z - 1;
+  const clamp = (c) => Math.min(Math.max(c, 0), last);
+  const old = new Map();
+  container.querySelectorAll('img').forEach((img) => {
+    const params = new URL(img.src).searchParams;
+    old.set(`${params.get('x')}/${params.get('y')}`, img);
+  });
+  const first = container.firstElementChild;
+  for (let y = clamp(Math.floor(view.y)); y <= clamp(Math.ceil(view.y + 2) - 1); y++) {
+    for (let x = clamp(Math.floor(view.x)); x <= clamp(Math.ceil(view.x + 2) - 1); x++) {
+      const key = `${x}/${y}`;
+      const img = old.get(key) || container.insertBefore(tileImg(container, view, x, y), first);
+      old.delete(key);
+      img.style.left = `${((x - view.x) * view.tilePx).toFixed(1)}px`;
+      img.style.top = `${((y - view.y) * view.tilePx).toFixed(1)}px`;
+      img.style.width = `${view.tilePx.toFixed(1)}px`;
     }
+  }
+  old.forEach((img) => img.remove());
+}
+
+// the server only knows the client hint (if sent), the size of the view may also have changed
+function scaleTiles(container, view) {
+  const scale = tileScale(view);
+  container.querySelectorAll('img').forEach((img) => {
+    const url = new URL(img.src);
+    if (Number(url.searchParams.get('dpr') || 1) === scale) return;
+    img.classList.remove('missing');
+    img.src = setScale(url, scale);
+  });
+}
+
+function layout(container) {
+  const view = readView(container);
+  layoutTiles(container, view);
+  scaleTiles(container, view);
+  container.querySelectorAll('[data-tile-x]').forEach((elem) => {
+    placeInView(view, elem, Number(elem.dataset.tileX), Number(elem.dataset.tileY));
+  });
+}
+
+// the deltas in the url are relative to the view centered on the reference point
+function viewDelta(container) {
+  const data = container.dataset;
+  const n = 2 ** Number(data.z);
+  const x0 = lonX(n, Number(data.lon)) - 1;
+  const y0 = latY(n, Number(data.lat)) - 1;
+  return { dx: Number(data.x) - x0, dy: Number(data.y) - y0 };
+}
+
+function pan(container, dxPx, dyPx) {
+  const view = readView(container);
+  const d = viewDelta(container);
+  const clamp = (c, delta) => Math.min(Math.max(c, -maxDelta - delta), maxDelta - delta);
+  container.dataset.x = view.x + clamp(-dxPx / view.tilePx, d.dx);
+  container.dataset.y = view.y + clamp(-dyPx / view.tilePx, d.dy);
+  layout(container);
+  clearTimeout(container.refreshTimer);
+  container.refreshTimer = setTimeout(() => refreshStations(container), refreshDelay);
+}
+
+// same as the rows in stations.html, the counts are set by updateStations
+function stationRow(main, station) {
+  const row = document.createElement('tr');
+  row.id = `station-${station.id}`;
+  row.dataset.station = station.id;
+  const link = document.createElement('a');
+  link.href = `#pin-${station.id}`;
+  link.textContent = station.id;
+  link.setAttribute('aria-label', main.dataset.showOnMap.replace('{}', station.id));
+  row.insertCell().append(link);
+  const cells = [
+    [station.name, ''],
+    ['', 'count'],
+    ['', 'status'],
+    [main.dataset.distance.replace('{}', station.distance - (station.distance % 10)), ''],
+  ];
+  cells.forEach(([text, className]) => {
+    const cell = row.insertCell();
+    cell.textContent = text;
+    cell.className = className;
+  });
+  const button = document.createElement('button');
+  button.className = 'star';
+  button.dataset.id = station.id;
+  button.setAttribute('aria-label', main.dataset.favorite);
+  button.dataset.star = main.dataset.star;
+  button.dataset.unstar = main.dataset.unstar;
+  const starred = readFavorites().includes(station.id);
+  button.setAttribute('aria-pressed', starred);
+  button.title = starred ? main.dataset.unstar : main.dataset.star;
+  button.textContent = starred ? '★' : '☆';
+  row.insertCell().append(button);
+  return row;
+}
+
+function stationPin(view, station) {
+  const pin = document.createElement('a');
+  pin.className = 'pin';
+  pin.id = `pin-${station.id}`;
+  pin.href = `#station-${station.id}`;
+  pin.dataset.station = station.id;
+  pin.dataset.name = station.name;
+  pin.textContent = station.id;
+  placeLonLat(view, pin, station.lon, station.lat);
+  return pin;
+}
+
+// replace the stations with the ones in the current view
+function showStations(main, container, snapshot) {
+  const view = readView(container);
+  const inView = snapshot.stations.filter((s) => relCoord(view, s.lon, s.lat));
+  container.querySelectorAll('.pin').forEach((pin) => pin.remove());
+  const marker = container.querySelector('.accuracy, .here');
+  inView.forEach((s) => container.insertBefore(stationPin(view, s), marker));
+  const rows = main.querySelector('tbody');
+  rows.replaceChildren(...inView.map((s) => stationRow(main, s)));
+  updateStations(main, { ...snapshot, stations: inView });
+}
+
+async function refreshStations(container) {
+  const main = container.closest('main');
+  const d = viewDelta(container);
+  const params = new URLSearchParams(window.location.search);
+  params.set('dx', d.dx.toFixed(2));
+  params.set('dy', d.dy.toFixed(2));
+  history.replaceState(null, '', `?${params}`);
+
+  const query = new URLSearchParams({
+    lon: container.dataset.lon,
+    lat: container.dataset.lat,
+    dx: d.dx.toFixed(2),
+    dy: d.dy.toFixed(2),
+  });
+  try {
+    const resp = await fetch(`/api/nearby-stations?${query}`);
+    if (!resp.ok) return;
+    showStations(main, container, await resp.json());
+    main.dataset.live = `/api/nearby-stations/live?${query}`;
+    startLive(main);
+  } catch {
+    // offline, the counts of the previous view are kept
+  }
+}
+
+// the view covers the same area in any size, so only the positions change
+function fitView(container) {
+  const width = container.parentElement.clientWidth;
+  const pixels = Math.round(Math.min(Math.max(width, minPixels), maxPixels));
+  const view = readView(container);
+  if (pixels === view.pixels) return;
+  const ratio = pixels / view.pixels;
+  container.dataset.pixels = pixels;
+  container.dataset.tilePx = pixels / (view.pixels / view.tilePx);
+  container.style.width = `${pixels}px`;
+  container.style.height = `${pixels}px`;
+  const accuracy = container.querySelector('.accuracy');
+  if (accuracy) {
+    accuracy.style.width = `${parseFloat(accuracy.style.width) * ratio}px`;
+    accuracy.style.height = `${parseFloat(accuracy.style.height) * ratio}px`;
+  }
+  layout(container);
+  const params = new URLSearchParams(window.location.search);
+  params.set('px', pixels);
+  history.replaceState(null, '', `?${params}`);
+}
+
+function handleDrag(container) {
+  let last = null;
+  container.addEventListener('pointerdown', (event) => {
+    // the pins are links to the rows of the table
+    if (event.target.closest('a')) return;
+    last = { x: event.clientX, y: event.clientY };
+    container.setPointerCapture(event.pointerId);
+    container.classList.add('dragging');
+  });
+  container.addEventListener('pointermove', (event) => {
+    if (!last) return;
+    pan(container, event.clientX - last.x, event.clientY - last.y);
+    last = { x: event.clientX, y: event.clientY };
+  });
+  const end = () => {
+    last = null;
+    container.classList.remove('dragging');
+  };
+  container.addEventListener('pointerup', end);
+  container.addEventListener('pointercancel', end);
+}
+
+function handleKeys(container) {
+  const steps = {
+    ArrowLeft: [1, 0],
+    ArrowRight: [-1, 0],
+    ArrowUp: [0, 1],
+    ArrowDown: [0, -1],
+  };
+  container.addEventListener('keydown', (event) => {
+    const step = steps[event.key];
+    if (!step) return;
+    event.preventDefault();
+    const px = keyStep * Number(container.dataset.tilePx);
+    pan(container, step[0] * px, step[1] * px);
   });
 }
 
 function move() {
-  handleSwipe(document.querySelector('.img-container'));
+  const container = document.querySelector('.img-container');
+  if (!container) return;
+  markMissingTiles(container);
+  // the server placed the pins, remember where they are in tiles
+  const view = readView(container);
+  container.querySelectorAll('.pin, .here, .accuracy').forEach((elem) => {
+    const tileX = view.x + parseFloat(elem.style.left) / view.tilePx;
+    const tileY = view.y + parseFloat(elem.style.top) / view.tilePx;
+    placeInView(view, elem, tileX, tileY);
+  });
+  fitView(container);
+  scaleTiles(container, readView(container));
+  window.addEventListener('resize', () => fitView(container));
+  handleDrag(container);
+  handleKeys(container);
 }
 
-window.onload = move;
+window.addEventListener('load', move);
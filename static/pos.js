@@ -1,11 +1,11 @@
-function redirectLatLon(lat, lon) {
-  window.location.replace(`?lat=${lat}&lon=${lon}`);
+function redirectLatLon(lat, lon, accuracy) {
+  window.location.replace(`?lat=${lat}&lon=${lon}&accuracy=${Math.round(accuracy)}`);
 }
 
 function getLocation() {
   if (navigator.geolocation) {
     navigator.geolocation.getCurrentPosition((pos) => {
-      redirectLatLon(pos.coords.latitude, pos.coords.longitude);
+      redirectLatLon(pos.coords.latitude, pos.coords.longitude, pos.coords.accuracy);
     });
   }
 }
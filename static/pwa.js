@@ -0,0 +1,24 @@
+// register the service worker with the same version as this script
+function registerServiceWorker() {
+  if (!navigator.serviceWorker) return;
+  const version = new URL(document.currentScript.src).searchParams.get('v');
+  navigator.serviceWorker.register(`/sw.js?v=${version}`);
+}
+
+// the service worker marks the pages served from its cache when offline
+function showOffline() {
+  const main = document.querySelector('main[data-offline-copy]');
+  if (!main) return;
+  const fetched = new Date(Number(main.dataset.fetched) * 1000);
+  const time = fetched.toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' });
+  const banner = document.createElement('p');
+  banner.className = 'stale';
+  banner.setAttribute('role', 'status');
+  banner.textContent = main.dataset.offline.replace('{}', time);
+  document.querySelector('.stale')?.remove();
+  main.prepend(banner);
+  window.addEventListener('online', () => window.location.reload());
+}
+
+registerServiceWorker();
+window.addEventListener('load', showOffline);
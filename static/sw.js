@@ -0,0 +1,97 @@
+// Service worker: the shell is cached on install, pages are fetched from the network and
+// the latest ones are shown when offline, tiles are served from the cache and refreshed
+// in the background. The version (hash of the static files) comes from the url.
+const version = new URL(self.location).searchParams.get('v');
+const shellCache = `shell-${version}`;
+const pageCache = 'pages';
+const tileCache = 'tiles';
+const maxTiles = 200;
+const maxPages = 20;
+// fallback for pages that were never viewed
+const lastPage = '/last-page';
+
+const shellPaths = ['/style.css', '/bike.svg', '/pos.js', '/move.js', '/live.js', '/pwa.js', '/favorites.js', '/here.js', '/theme.js'];
+const shell = shellPaths.map((path) => `${path}?v=${version}`);
+
+self.addEventListener('install', (event) => {
+  event.waitUntil(caches.open(shellCache).then((cache) => cache.addAll(shell)));
+  self.skipWaiting();
+});
+
+self.addEventListener('activate', (event) => {
+  const current = [shellCache, pageCache, tileCache];
+  event.waitUntil(caches.keys()
+    .then((keys) => Promise.all(keys.filter((k) => !current.includes(k)).map((k) => caches.delete(k))))
+    .then(() => self.clients.claim()));
+});
+
+// drop the oldest entries so that the cache does not grow without bounds
+async function trim(cache, max) {
+  const keys = await cache.keys();
+  await Promise.all(keys.slice(0, Math.max(keys.length - max, 0)).map((k) => cache.delete(k)));
+}
+
+async function networkFirst(request) {
+  const cache = await caches.open(pageCache);
+  try {
+    const response = await fetch(request);
+    if (response.ok) {
+      const html = await response.clone().text();
+      await cache.put(request, response.clone());
+      if (html.includes('data-fetched')) {
+        await cache.put(lastPage, new Response(html, { headers: response.headers }));
+      }
+      await trim(cache, maxPages);
+    }
+    return response;
+  } catch (err) {
+    const cached = await cache.match(request) ?? await cache.match(lastPage);
+    if (!cached) throw err;
+    // tell the page that it is not fresh
+    const html = (await cached.text()).replace('<main ', '<main data-offline-copy ');
+    return new Response(html, { headers: cached.headers });
+  }
+}
+
+async function staleWhileRevalidate(event) {
+  const cache = await caches.open(tileCache);
+  const cached = await cache.match(event.request);
+  const refresh = fetch(event.request).then(async (response) => {
+    if (response.ok) {
+      await cache.put(event.request, response.clone());
+      await trim(cache, maxTiles);
+    }
+    return response;
+  });
+  if (cached) {
+    event.waitUntil(refresh.catch(() => {}));
+    return cached;
+  }
+  return refresh;
+}
+
+// a miss is usually a newer version (during a deploy), which must not get the old files since
+// the pages and the scripts go together. Any version is better than nothing when offline.
+async function cacheFirst(request) {
+  const cached = await caches.match(request);
+  if (cached) return cached;
+  try {
+    return await fetch(request);
+  } catch (e) {
+    const old = await caches.match(request, { ignoreSearch: true });
+    if (old) return old;
+    throw e;
+  }
+}
+
+self.addEventListener('fetch', (event) => {
+  const url = new URL(event.request.url);
+  if (event.request.method !== 'GET' || url.origin !== self.location.origin) return;
+  if (event.request.mode === 'navigate') {
+    event.respondWith(networkFirst(event.request));
+  } else if (url.pathname === '/img') {
+    event.respondWith(staleWhileRevalidate(event));
+  } else if (shellPaths.includes(url.pathname)) {
+    event.respondWith(cacheFirst(event.request));
+  }
+});
@@ -0,0 +1,52 @@
+// the theme toggle in the navigation. The theme is kept in a cookie so that the pages are
+// rendered in it (see theme.rs), the map switches between the light and dark basemaps of
+// the page (data-light-style and data-dark-style, see MapStyles).
+
+const themes = ['auto', 'light', 'dark', 'contrast'];
+const themeCookieAge = 365 * 24 * 60 * 60;
+const prefersDark = window.matchMedia('(prefers-color-scheme: dark)');
+
+// same as Theme::dark_tiles, except that auto is resolved here
+function darkTiles(theme) {
+  return theme === 'dark' || (theme === 'auto' && prefersDark.matches);
+}
+
+function applyTileStyle(theme) {
+  const container = document.querySelector('.img-container');
+  if (!container) return;
+  const data = container.dataset;
+  const style = darkTiles(theme) ? data.darkStyle : data.lightStyle;
+  if (data.tileStyle === style) return;
+  data.tileStyle = style;
+  container.querySelector('.attribution').textContent = darkTiles(theme)
+    ? data.darkAttribution
+    : data.lightAttribution;
+  container.querySelectorAll('img').forEach((img) => {
+    const url = new URL(img.src);
+    url.searchParams.set('style', style);
+    img.classList.remove('missing');
+    img.src = url;
+  });
+}
+
+function setTheme(button, theme) {
+  document.documentElement.dataset.theme = theme;
+  document.cookie = `theme=${theme}; Path=/; Max-Age=${themeCookieAge}; SameSite=Lax`;
+  const name = button.dataset[`theme${theme[0].toUpperCase()}${theme.slice(1)}`];
+  button.textContent = button.dataset.label.replace('{}', name);
+  applyTileStyle(theme);
+}
+
+function theme() {
+  const current = () => document.documentElement.dataset.theme;
+  const button = document.querySelector('button.theme');
+  if (button) {
+    button.addEventListener('click', () => {
+      setTheme(button, themes[(themes.indexOf(current()) + 1) % themes.length]);
+    });
+  }
+  applyTileStyle(current());
+  prefersDark.addEventListener('change', () => applyTileStyle(current()));
+}
+
+window.addEventListener('load', theme);
@@ -1,18 +1,18 @@
-use bikes::{AppConf, Tile};
+use bikes::{AppConf, Digitransit, Tile};
 
 #[tokio::test]
 #[ignore]
 async fn img_request_works() {
-    let api_key = AppConf::from_env().unwrap().api_key();
+    let digitransit = Digitransit::new(AppConf::from_env().unwrap().api_key()).unwrap();
     let (lon, lat) = (24.9314, 60.16847);
     let tile0 = Tile::ref_point(15, lon, lat);
-    let img0 = tile0.img_request(&api_key).await.unwrap();
+    let img0 = tile0.img_request(&digitransit).await.unwrap();
     assert!(img0.len() >= 100000);
 
     // different result with different coordinates
     let (lon, lat) = (24.94, 60.17);
     let tile1 = Tile::ref_point(15, lon, lat);
-    let img1 = tile1.img_request(&api_key).await.unwrap();
+    let img1 = tile1.img_request(&digitransit).await.unwrap();
     assert_ne!(img0, img1);
     assert!(img1.len() >= 100000);
 }
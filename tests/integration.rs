@@ -1,16 +1,21 @@
-use bikes::{AppConf, Station, StationData, Tile};
+use bikes::{AppConf, Digitransit, Lang, Station, StationData, Tile, Viewport};
 
 #[tokio::test]
 #[ignore]
 async fn station_data_get_works() {
-    let api_key = AppConf::from_env().unwrap().api_key();
+    let digitransit = Digitransit::new(AppConf::from_env().unwrap().api_key()).unwrap();
     let (lon, lat) = (24.94, 60.17);
+    // the view with the reference tile in the upper left corner, so that the coordinates
+    // are the same as when the view was aligned to the tiles
     let ref_point = Tile::ref_point(15, lon, lat);
+    let view = Viewport::new(lon, lat, 15, 350);
+    let view = view.moved((ref_point.x as f64 - view.x(), ref_point.y as f64 - view.y()));
 
-    let station_data_n = StationData::get(&api_key, lon, lat, 1000, 2).await.unwrap();
-    let px = 350;
+    let station_data_n = StationData::get(&digitransit, lon, lat, 1000, 2, Lang::En)
+        .await
+        .unwrap();
 
-    let stations = station_data_n.into_stations(&ref_point, px);
+    let stations = station_data_n.into_stations(&view);
     // response matches request limit
     // Station {
     let station0 = Station {
@@ -20,6 +25,7 @@ async fn station_data_get_works() {
         x: 188,
         y: 119,
         distance: 99,
+        favorite: false,
     };
     let station1 = Station {
         id: String::from("024"),
@@ -28,6 +34,7 @@ async fn station_data_get_works() {
         x: 155,
         y: 147,
         distance: 183,
+        favorite: false,
     };
 
     let stations_exp = [station0, station1];
@@ -43,25 +50,24 @@ async fn station_data_get_works() {
 #[tokio::test]
 #[ignore]
 async fn station_data_get_limits_work() {
-    let api_key = AppConf::from_env().unwrap().api_key();
+    let digitransit = Digitransit::new(AppConf::from_env().unwrap().api_key()).unwrap();
     let (lon, lat) = (24.9314, 60.16847);
-    let ref_point = Tile::ref_point(15, lon, lat);
+    let view = Viewport::new(lon, lat, 15, 350);
 
     let n = 5;
-    let station_data_n = StationData::get(&api_key, lon, lat, 1000, n as u8)
+    let station_data_n = StationData::get(&digitransit, lon, lat, 1000, n as u8, Lang::En)
         .await
         .unwrap();
-    let px = 350;
 
-    let stations_n = station_data_n.into_stations(&ref_point, px);
+    let stations_n = station_data_n.into_stations(&view);
     // response matches request limit
     assert_eq!(stations_n.len(), n);
 
     let max_dist = 300;
-    let station_data_dist = StationData::get(&api_key, lon, lat, max_dist, 10)
+    let station_data_dist = StationData::get(&digitransit, lon, lat, max_dist, 10, Lang::En)
         .await
         .unwrap();
-    let stations_dist = station_data_dist.into_stations(&ref_point, px);
+    let stations_dist = station_data_dist.into_stations(&view);
     assert!(!stations_dist.is_empty());
 
     // stations match request limit
@@ -79,3 +85,52 @@ async fn station_data_get_limits_work() {
         assert_eq!(stations_n[i].distance, stations_dist[i].distance);
     }
 }
+
+#[tokio::test]
+#[ignore]
+async fn station_data_by_id_and_bbox_work() {
+    let digitransit = Digitransit::new(AppConf::from_env().unwrap().api_key()).unwrap();
+    let (lon, lat) = (24.9314, 60.16847);
+    let view = Viewport::new(lon, lat, 15, 350);
+
+    let in_view = StationData::get_in_bbox(&digitransit, view.tiles().bbox(), Lang::En)
+        .await
+        .unwrap();
+    let obs = in_view.observations();
+    assert!(!obs.is_empty());
+    let bbox = view.tiles().bbox();
+    for s in obs {
+        assert!((bbox.min_lon..=bbox.max_lon).contains(&s.lon), "{}", s.id);
+        assert!((bbox.min_lat..=bbox.max_lat).contains(&s.lat), "{}", s.id);
+    }
+
+    // the corners of the view are less than 1000m from the center, so all the stations within
+    // the view are among the nearest ones
+    let nearest = StationData::get(&digitransit, lon, lat, 1000, 200, Lang::En)
+        .await
+        .unwrap()
+        .into_stations(&view);
+    assert!(!nearest.is_empty());
+    let mut expected: Vec<_> = nearest.into_iter().map(|s| s.id).collect();
+    let mut stations: Vec<_> = in_view
+        .clone()
+        .into_stations(&view)
+        .into_iter()
+        .map(|s| s.id)
+        .collect();
+    expected.sort();
+    stations.sort();
+    assert_eq!(stations, expected);
+
+    let station = StationData::get_by_id(&digitransit, &obs[0].id, Lang::En)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(station.id, obs[0].id);
+    assert_eq!(station.name, obs[0].name);
+
+    let missing = StationData::get_by_id(&digitransit, "no-such-station", Lang::En)
+        .await
+        .unwrap();
+    assert!(missing.is_none());
+}